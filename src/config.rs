@@ -0,0 +1,563 @@
+use crate::weather_aggregator::{AGGREGATED_FIELDS, AggregationStrategy, CustomProviderDefinition};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+pub const KNOWN_PROVIDERS: &[&str] = &["open_weather", "weatherbit", "open_meteo", "nws", "met_norway"];
+// providers with free public api, they are active unless disabled
+const KEYLESS_PROVIDERS: &[&str] = &["open_meteo", "nws", "met_norway"];
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+const MAX_FORECAST_HORIZON_DAYS: usize = 16;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen_address: String,
+    pub request_timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    pub user_agent: String,
+    pub forecast_horizon_days: usize,
+    pub cache: CacheConfig,
+    pub retry: RetryConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub aggregation: AggregationConfig,
+    pub providers: BTreeMap<String, ProviderConfig>,
+    // env overrides which could not be parsed, reported by validate along with the rest
+    #[serde(skip)]
+    env_problems: Vec<String>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub current_ttl_secs: u64,
+    pub forecast_ttl_secs: u64
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: u32,
+    pub open_secs: u64
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AggregationConfig {
+    pub strategy: AggregationStrategy,
+    pub trim_fraction: f64,
+    pub fields: BTreeMap<String, AggregationStrategy>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderConfig {
+    pub enabled: bool,
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub weight: f64,
+    pub custom: Option<CustomProviderDefinition>
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let providers = KNOWN_PROVIDERS
+            .iter()
+            .map(|name| (name.to_string(), ProviderConfig::default()))
+            .collect();
+
+        Self {
+            listen_address: String::from("127.0.0.1:7878"),
+            request_timeout_secs: 10,
+            connect_timeout_secs: 3,
+            user_agent: format!("weather-reports/{} (+https://github.com/daniilsunyaev/weather_reports)", env!("CARGO_PKG_VERSION")),
+            forecast_horizon_days: MAX_FORECAST_HORIZON_DAYS,
+            cache: CacheConfig::default(),
+            retry: RetryConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            aggregation: AggregationConfig::default(),
+            providers,
+            env_problems: vec![]
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { current_ttl_secs: 10 * 60, forecast_ttl_secs: 60 * 60 }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self { max_retries: 2, base_delay_ms: 200, max_delay_ms: 2000 }
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self { failure_threshold: 5, open_secs: 30 }
+    }
+}
+
+impl Default for AggregationConfig {
+    fn default() -> Self {
+        Self { strategy: AggregationStrategy::Mean, trim_fraction: 0.25, fields: BTreeMap::new() }
+    }
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        Self { enabled: true, api_key: None, base_url: None, timeout_secs: None, weight: 1.0, custom: None }
+    }
+}
+
+impl Config {
+    // Reads CONFIG_PATH (or ./config.toml when present), then applies env overrides.
+    pub fn load() -> Result<Config, ConfigError> {
+        dotenv::dotenv().ok();
+
+        let path = std::env::var("CONFIG_PATH").ok();
+        let mut config = match &path {
+            Some(path) => Self::from_file(path)?,
+            None if std::path::Path::new(DEFAULT_CONFIG_PATH).exists() => Self::from_file(DEFAULT_CONFIG_PATH)?,
+            None => Config::default()
+        };
+        config.apply_env(|name| std::env::var(name).ok());
+        config.validate()?;
+        Ok(config)
+    }
+
+    // a [providers.*] table replaces the whole default map, so built-in providers
+    // which are not mentioned in the file are brought back with default settings
+    pub fn from_toml(source: &str) -> Result<Config, ConfigError> {
+        let mut config: Config = toml::from_str(source).map_err(|error| ConfigError::single(format!("invalid config: {}", error)))?;
+        for name in KNOWN_PROVIDERS {
+            config.providers.entry(name.to_string()).or_default();
+        }
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Config, ConfigError> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|error| ConfigError::single(format!("can't read config file {}: {}", path, error)))?;
+        Self::from_toml(&source)
+    }
+
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        if let Some(listen_address) = var("LISTEN_ADDRESS") {
+            self.listen_address = listen_address;
+        }
+        override_number(&mut self.request_timeout_secs, "REQUEST_TIMEOUT_SECS", &var, &mut self.env_problems);
+        override_number(&mut self.connect_timeout_secs, "CONNECT_TIMEOUT_SECS", &var, &mut self.env_problems);
+        if let Some(user_agent) = var("USER_AGENT") {
+            self.user_agent = user_agent;
+        }
+        override_number(&mut self.forecast_horizon_days, "FORECAST_HORIZON_DAYS", &var, &mut self.env_problems);
+        override_number(&mut self.cache.current_ttl_secs, "CURRENT_CACHE_TTL_SECS", &var, &mut self.env_problems);
+        override_number(&mut self.cache.forecast_ttl_secs, "FORECAST_CACHE_TTL_SECS", &var, &mut self.env_problems);
+        override_number(&mut self.retry.max_retries, "MAX_RETRIES", &var, &mut self.env_problems);
+        override_number(&mut self.aggregation.strategy, "AGGREGATION_STRATEGY", &var, &mut self.env_problems);
+
+        let custom_providers: Vec<String> = self.providers
+            .iter()
+            .filter(|(_, provider)| provider.custom.is_some())
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in KNOWN_PROVIDERS.iter().copied().chain(custom_providers.iter().map(String::as_str)) {
+            let prefix = name.to_uppercase();
+            let api_key = var(&api_key_env_var(name));
+            let base_url = var(&format!("{}_BASE_URL", prefix));
+            let enabled = var(&format!("{}_ENABLED", prefix));
+            if api_key.is_none() && base_url.is_none() && enabled.is_none() {
+                continue;
+            }
+
+            let provider = self.providers.entry(name.to_string()).or_default();
+            if api_key.is_some() {
+                provider.api_key = api_key;
+            }
+            if base_url.is_some() {
+                provider.base_url = base_url;
+            }
+            if let Some(enabled) = enabled {
+                match parse_flag(&enabled) {
+                    Some(enabled) => provider.enabled = enabled,
+                    None => self.env_problems.push(format!("{}_ENABLED has invalid value {:?}: expected true, false, 1 or 0", prefix, enabled))
+                }
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = self.env_problems.clone();
+
+        if self.listen_address.parse::<SocketAddr>().is_err() {
+            problems.push(format!("listen_address should look like 127.0.0.1:7878, got {:?}", self.listen_address));
+        }
+        if self.request_timeout_secs == 0 {
+            problems.push(String::from("request_timeout_secs should be positive"));
+        }
+        if self.connect_timeout_secs == 0 {
+            problems.push(String::from("connect_timeout_secs should be positive"));
+        }
+        if self.user_agent.trim().is_empty() {
+            problems.push(String::from("user_agent should not be empty"));
+        }
+        if self.retry.base_delay_ms > self.retry.max_delay_ms {
+            problems.push(String::from("retry.base_delay_ms should not exceed retry.max_delay_ms"));
+        }
+        if self.circuit_breaker.failure_threshold == 0 {
+            problems.push(String::from("circuit_breaker.failure_threshold should be positive"));
+        }
+        if self.forecast_horizon_days == 0 || self.forecast_horizon_days > MAX_FORECAST_HORIZON_DAYS {
+            problems.push(format!("forecast_horizon_days should be within 1..{}", MAX_FORECAST_HORIZON_DAYS));
+        }
+        if !(0.0..0.5).contains(&self.aggregation.trim_fraction) {
+            problems.push(String::from("aggregation.trim_fraction should be within 0..0.5"));
+        }
+        for field in self.aggregation.fields.keys() {
+            if !AGGREGATED_FIELDS.contains(&field.as_str()) {
+                problems.push(format!("unknown aggregation field {}, expected one of: {}", field, AGGREGATED_FIELDS.join(", ")));
+            }
+        }
+
+        for (name, provider) in &self.providers {
+            match &provider.custom {
+                None if !KNOWN_PROVIDERS.contains(&name.as_str()) => {
+                    problems.push(format!(
+                        "unknown provider {}, expected one of: {} or a custom provider with [providers.{}.custom] definition",
+                        name, KNOWN_PROVIDERS.join(", "), name
+                    ));
+                    continue;
+                },
+                Some(_) if KNOWN_PROVIDERS.contains(&name.as_str()) => {
+                    problems.push(format!("{} is a built-in provider, custom definition should be named differently", name));
+                    continue;
+                },
+                _ => {}
+            }
+            if !provider.enabled {
+                continue;
+            }
+            if let Some(base_url) = &provider.base_url {
+                if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+                    problems.push(format!("{} base_url should be an http(s) url, got {:?}", name, base_url));
+                }
+            }
+            if provider.timeout_secs == Some(0) {
+                problems.push(format!("{} timeout_secs should be positive", name));
+            }
+            if !(provider.weight.is_finite() && provider.weight > 0.0) {
+                problems.push(format!("{} weight should be a positive number", name));
+            }
+            if let Some(custom) = &provider.custom {
+                if provider.base_url.is_none() {
+                    problems.push(format!("{} base_url should be set for custom provider", name));
+                }
+                problems.extend(custom.problems(name));
+            }
+        }
+
+        // keyless providers are active by default, so this only happens when all of them are disabled
+        if self.active_providers().next().is_none() {
+            let api_key_vars: Vec<String> = KNOWN_PROVIDERS
+                .iter()
+                .filter(|name| !KEYLESS_PROVIDERS.contains(name))
+                .map(|name| api_key_env_var(name))
+                .collect();
+            problems.push(format!(
+                "no provider is active, enable one of {} or set api key for at least one of: {}",
+                KEYLESS_PROVIDERS.join(", "), api_key_vars.join(", ")
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError { problems })
+        }
+    }
+
+    pub fn active_providers(&self) -> impl Iterator<Item = (&str, &ProviderConfig)> {
+        self.providers
+            .iter()
+            .filter(|(name, provider)| (KNOWN_PROVIDERS.contains(&name.as_str()) || provider.custom.is_some()) && provider.is_active(name))
+            .map(|(name, provider)| (name.as_str(), provider))
+    }
+
+    pub fn inactive_providers(&self) -> Vec<(&str, &'static str)> {
+        let custom_providers = self.providers
+            .iter()
+            .filter(|(_, provider)| provider.custom.is_some())
+            .map(|(name, _)| name.as_str());
+        KNOWN_PROVIDERS
+            .iter()
+            .copied()
+            .chain(custom_providers)
+            .filter_map(|name| match self.providers.get(name) {
+                Some(provider) if provider.is_active(name) => None,
+                Some(provider) if provider.enabled => Some((name, "api key is not configured")),
+                _ => Some((name, "disabled"))
+            })
+            .collect()
+    }
+
+    pub fn provider_timeout(&self, provider: &ProviderConfig) -> Duration {
+        Duration::from_secs(provider.timeout_secs.unwrap_or(self.request_timeout_secs))
+    }
+}
+
+fn api_key_env_var(provider: &str) -> String {
+    match provider {
+        "open_weather" => String::from("OPEN_WEATHER_APPID"),
+        _ => format!("{}_API_KEY", provider.to_uppercase())
+    }
+}
+
+fn override_number<T>(value: &mut T, name: &str, var: &impl Fn(&str) -> Option<String>, problems: &mut Vec<String>)
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display
+{
+    if let Some(env_value) = var(name) {
+        match env_value.parse() {
+            Ok(parsed) => *value = parsed,
+            Err(error) => problems.push(format!("{} has invalid value {:?}: {}", name, env_value, error))
+        }
+    }
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None
+    }
+}
+
+impl ProviderConfig {
+    fn is_active(&self, name: &str) -> bool {
+        let keyless = match &self.custom {
+            Some(custom) => !custom.needs_api_key(),
+            None => KEYLESS_PROVIDERS.contains(&name)
+        };
+        self.enabled && (keyless || !self.api_key.as_deref().unwrap_or_default().is_empty())
+    }
+}
+
+impl ConfigError {
+    fn single(problem: String) -> Self {
+        Self { problems: vec![problem] }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid configuration:\n  {}", self.problems.join("\n  "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn it_reads_toml_config() {
+        let config = Config::from_toml(r#"
+            listen_address = "0.0.0.0:8080"
+            forecast_horizon_days = 5
+
+            [cache]
+            current_ttl_secs = 60
+
+            [aggregation]
+            strategy = "weighted_mean"
+            fields = { precipitation = "median" }
+
+            [providers.weatherbit]
+            api_key = "key"
+            base_url = "http://localhost:9000"
+            timeout_secs = 3
+            weight = 2.5
+        "#).unwrap();
+
+        assert_eq!(config.listen_address, "0.0.0.0:8080");
+        assert_eq!(config.forecast_horizon_days, 5);
+        assert_eq!(config.cache.current_ttl_secs, 60);
+        assert_eq!(config.cache.forecast_ttl_secs, 3600);
+        assert_eq!(config.providers.keys().collect::<Vec<_>>(), vec!["met_norway", "nws", "open_meteo", "open_weather", "weatherbit"]);
+        assert_eq!(config.provider_timeout(&config.providers["weatherbit"]), Duration::from_secs(3));
+        assert_eq!(config.providers["weatherbit"].weight, 2.5);
+        assert_eq!(config.aggregation.strategy, AggregationStrategy::WeightedMean);
+        assert_eq!(config.aggregation.fields["precipitation"], AggregationStrategy::Median);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn it_keeps_providers_missing_from_toml() {
+        let config = Config::from_toml("[providers.open_weather]\napi_key = \"key\"").unwrap();
+
+        assert_eq!(config.active_providers().map(|(name, _)| name).collect::<Vec<_>>(), vec!["met_norway", "nws", "open_meteo", "open_weather"]);
+        assert_eq!(config.inactive_providers(), vec![("weatherbit", "api key is not configured")]);
+    }
+
+    #[test]
+    fn it_requires_some_active_provider() {
+        let mut config = Config::from_toml(r#"
+            [providers.open_meteo]
+            enabled = false
+
+            [providers.nws]
+            enabled = false
+
+            [providers.met_norway]
+            enabled = false
+        "#).unwrap();
+        config.apply_env(env(&[]));
+
+        assert_eq!(config.validate().unwrap_err().problems, vec![
+            "no provider is active, enable one of open_meteo, nws, met_norway or set api key for at least one of: OPEN_WEATHER_APPID, WEATHERBIT_API_KEY"
+        ]);
+
+        config.apply_env(env(&[("NWS_ENABLED", "true")]));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn it_overrides_config_with_env_vars() {
+        let mut config = Config::from_toml("[providers.weatherbit]\napi_key = \"from file\"").unwrap();
+        config.apply_env(env(&[
+            ("LISTEN_ADDRESS", "0.0.0.0:80"),
+            ("WEATHERBIT_API_KEY", "from env"),
+            ("OPEN_WEATHER_APPID", "appid"),
+            ("FORECAST_CACHE_TTL_SECS", "120")
+        ]));
+
+        assert_eq!(config.listen_address, "0.0.0.0:80");
+        assert_eq!(config.cache.forecast_ttl_secs, 120);
+        assert_eq!(config.providers["weatherbit"].api_key.as_deref(), Some("from env"));
+        assert_eq!(config.providers["open_weather"].api_key.as_deref(), Some("appid"));
+    }
+
+    #[test]
+    fn it_reports_unparsable_env_overrides() {
+        let mut config = Config::default();
+        config.apply_env(env(&[
+            ("REQUEST_TIMEOUT_SECS", "abc"),
+            ("AGGREGATION_STRATEGY", "medain"),
+            ("MAX_RETRIES", "3"),
+            ("NWS_ENABLED", "flase"),
+            ("MET_NORWAY_ENABLED", "0")
+        ]));
+
+        assert_eq!(config.request_timeout_secs, 10);
+        assert_eq!(config.retry.max_retries, 3);
+        assert!(config.providers["nws"].enabled);
+        assert!(!config.providers["met_norway"].enabled);
+        let problems = config.validate().unwrap_err().problems;
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("REQUEST_TIMEOUT_SECS has invalid value \"abc\""));
+        assert!(problems[1].starts_with("AGGREGATION_STRATEGY has invalid value \"medain\""));
+        assert_eq!(problems[2], "NWS_ENABLED has invalid value \"flase\": expected true, false, 1 or 0");
+    }
+
+    #[test]
+    fn it_reports_all_problems_at_once() {
+        let mut config = Config::from_toml(r#"
+            listen_address = "localhost"
+            forecast_horizon_days = 0
+
+            [aggregation]
+            fields = { visibility = "median" }
+
+            [providers.weatherbit]
+            base_url = "api.weatherbit.io"
+            weight = 0.0
+
+            [providers.accuweather]
+        "#).unwrap();
+        config.apply_env(env(&[("OPEN_METEO_ENABLED", "false"), ("NWS_ENABLED", "false"), ("MET_NORWAY_ENABLED", "false")]));
+
+        let problems = config.validate().unwrap_err().problems;
+        assert_eq!(problems.len(), 7);
+        assert!(problems.iter().any(|problem| problem.contains("unknown aggregation field visibility")));
+        assert!(problems.iter().any(|problem| problem.contains("no provider is active")));
+        assert!(problems.iter().any(|problem| problem.contains("unknown provider accuweather")));
+    }
+
+    #[test]
+    fn it_reads_custom_provider_definitions() {
+        let mut config = Config::from_toml(r#"
+            [providers.regional]
+            base_url = "https://weather.example.com/api"
+            weight = 0.5
+
+            [providers.regional.custom]
+            current_url = "/current?lat={lat}&lon={lon}"
+            api_key_header = "X-Api-Key"
+            fields = { time = "/dt", temperature = "/main/temp" }
+            units = { temperature = "kelvin" }
+
+            [providers.internal.custom]
+            forecast_url = "/daily/{city}"
+            fields = { time = "/dt", temperature = "/t" }
+        "#).unwrap();
+
+        let problems = config.validate().unwrap_err().problems;
+        assert_eq!(problems, vec!["internal base_url should be set for custom provider"]);
+
+        config.providers.get_mut("internal").unwrap().base_url = Some(String::from("http://localhost:9000"));
+        assert!(config.validate().is_ok());
+        assert!(config.inactive_providers().contains(&("regional", "api key is not configured")));
+        assert_eq!(config.active_providers().map(|(name, _)| name).collect::<Vec<_>>(), vec!["internal", "met_norway", "nws", "open_meteo"]);
+
+        config.apply_env(env(&[("REGIONAL_API_KEY", "key")]));
+        assert_eq!(config.providers["regional"].api_key.as_deref(), Some("key"));
+        assert_eq!(config.active_providers().map(|(name, _)| name).collect::<Vec<_>>(), vec!["internal", "met_norway", "nws", "open_meteo", "regional"]);
+    }
+
+    #[test]
+    fn it_runs_with_subset_of_providers() {
+        let mut config = Config::default();
+        config.apply_env(env(&[("WEATHERBIT_API_KEY", "key")]));
+
+        assert!(config.validate().is_ok());
+        assert_eq!(config.active_providers().map(|(name, _)| name).collect::<Vec<_>>(), vec!["met_norway", "nws", "open_meteo", "weatherbit"]);
+        assert_eq!(config.inactive_providers(), vec![("open_weather", "api key is not configured")]);
+
+        config.apply_env(env(&[("OPEN_METEO_ENABLED", "false"), ("NWS_ENABLED", "false"), ("MET_NORWAY_ENABLED", "false")]));
+        assert_eq!(config.active_providers().map(|(name, _)| name).collect::<Vec<_>>(), vec!["weatherbit"]);
+
+        config.apply_env(env(&[("OPEN_WEATHER_APPID", "appid"), ("OPEN_WEATHER_ENABLED", "false")]));
+        assert_eq!(config.inactive_providers(), vec![("open_weather", "disabled"), ("open_meteo", "disabled"), ("nws", "disabled"), ("met_norway", "disabled")]);
+    }
+
+    #[test]
+    fn it_rejects_unknown_fields() {
+        assert!(Config::from_toml("listen_adress = \"0.0.0.0:80\"").is_err());
+    }
+}
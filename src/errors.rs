@@ -0,0 +1,306 @@
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use crate::Location;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ProviderError {
+    UnknownLocation,
+    UnsupportedLocation(Location),
+    InvalidApiKey(Option<String>),
+    Timeout,
+    Parse(String),
+    Unavailable(String),
+    CircuitOpen
+}
+
+impl ProviderError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProviderError::UnknownLocation => "unknown_location",
+            ProviderError::UnsupportedLocation(_) => "unsupported_location",
+            ProviderError::InvalidApiKey(_) => "invalid_api_key",
+            ProviderError::Timeout => "timeout",
+            ProviderError::Parse(_) => "parse_error",
+            ProviderError::Unavailable(_) => "unavailable",
+            ProviderError::CircuitOpen => "circuit_open"
+        }
+    }
+
+    // failures which say something about provider health rather than about the request
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ProviderError::Timeout | ProviderError::Unavailable(_))
+    }
+}
+
+impl std::error::Error for ProviderError {}
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderError::UnknownLocation => write!(f, "location not found"),
+            ProviderError::UnsupportedLocation(location) => write!(f, "can't look up location {}", location),
+            ProviderError::InvalidApiKey(Some(message)) => write!(f, "api key was rejected: {}", message),
+            ProviderError::InvalidApiKey(None) => write!(f, "api key was rejected"),
+            ProviderError::Timeout => write!(f, "request timed out"),
+            ProviderError::Parse(message) => write!(f, "{}", message),
+            ProviderError::Unavailable(message) => write!(f, "{}", message),
+            ProviderError::CircuitOpen => write!(f, "provider is failing, requests are paused")
+        }
+    }
+}
+
+// reqwest 0.10 appends the request url to its messages, and the url carries the api key,
+// so the message is put together from the error kind and source only
+impl From<reqwest::Error> for ProviderError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            return ProviderError::Timeout;
+        }
+
+        let message = if let Some(status) = error.status() {
+            format!("unexpected response status {}", status)
+        } else if error.is_connect() {
+            "could not connect to provider".to_string()
+        } else if error.is_decode() {
+            "error decoding response body".to_string()
+        } else if error.is_body() {
+            "error reading response body".to_string()
+        } else {
+            "error sending request".to_string()
+        };
+        let message = match std::error::Error::source(&error) {
+            Some(source) => format!("{}: {}", message, source),
+            None => message
+        };
+
+        if error.is_decode() {
+            ProviderError::Parse(message)
+        } else {
+            ProviderError::Unavailable(message)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderFailure {
+    pub provider: &'static str,
+    pub error: &'static str,
+    pub detail: String
+}
+
+impl ProviderFailure {
+    pub fn new(provider: &'static str, error: &ProviderError) -> Self {
+        Self { provider, error: error.kind(), detail: error.to_string() }
+    }
+}
+
+#[derive(Debug)]
+pub enum WeatherError {
+    InvalidRequest(String),
+    UnknownLocation(Vec<ProviderFailure>),
+    DateNotCovered(String),
+    InvalidUpstreamKey(Vec<ProviderFailure>),
+    UpstreamTimeout(Vec<ProviderFailure>),
+    UpstreamParse(Vec<ProviderFailure>),
+    AllProvidersDown(Vec<ProviderFailure>)
+}
+
+impl WeatherError {
+    // Picks the most specific error which explains every provider failure,
+    // falling back to all-providers-down when providers failed differently.
+    pub fn from_failures(failures: Vec<ProviderFailure>) -> Self {
+        let kinds : Vec<&str> = failures
+            .iter()
+            .map(|failure| failure.error)
+            .filter(|kind| *kind != "unsupported_location")
+            .collect();
+
+        let all_failed_with = |kind: &str| !kinds.is_empty() && kinds.iter().all(|failure_kind| *failure_kind == kind);
+        if failures.is_empty() {
+            WeatherError::AllProvidersDown(failures)
+        } else if kinds.is_empty() || all_failed_with("unknown_location") {
+            WeatherError::UnknownLocation(failures)
+        } else if all_failed_with("invalid_api_key") {
+            WeatherError::InvalidUpstreamKey(failures)
+        } else if all_failed_with("timeout") {
+            WeatherError::UpstreamTimeout(failures)
+        } else if all_failed_with("parse_error") {
+            WeatherError::UpstreamParse(failures)
+        } else {
+            WeatherError::AllProvidersDown(failures)
+        }
+    }
+
+    pub fn failures(&self) -> &[ProviderFailure] {
+        match self {
+            WeatherError::InvalidRequest(_) | WeatherError::DateNotCovered(_) => &[],
+            WeatherError::UnknownLocation(failures)
+                | WeatherError::InvalidUpstreamKey(failures)
+                | WeatherError::UpstreamTimeout(failures)
+                | WeatherError::UpstreamParse(failures)
+                | WeatherError::AllProvidersDown(failures) => failures
+        }
+    }
+
+    fn problem_type(&self) -> &'static str {
+        match self {
+            WeatherError::InvalidRequest(_) => "invalid-request",
+            WeatherError::UnknownLocation(_) => "unknown-location",
+            WeatherError::DateNotCovered(_) => "date-not-covered",
+            WeatherError::InvalidUpstreamKey(_) => "invalid-upstream-key",
+            WeatherError::UpstreamTimeout(_) => "upstream-timeout",
+            WeatherError::UpstreamParse(_) => "upstream-parse-error",
+            WeatherError::AllProvidersDown(_) => "all-providers-down"
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            WeatherError::InvalidRequest(_) => "Invalid request",
+            WeatherError::UnknownLocation(_) => "Unknown location",
+            WeatherError::DateNotCovered(_) => "Date is not covered by providers",
+            WeatherError::InvalidUpstreamKey(_) => "Weather provider rejected api key",
+            WeatherError::UpstreamTimeout(_) => "Weather providers timed out",
+            WeatherError::UpstreamParse(_) => "Could not parse weather provider response",
+            WeatherError::AllProvidersDown(_) => "Could not find weather data"
+        }
+    }
+}
+
+impl std::error::Error for WeatherError {}
+impl fmt::Display for WeatherError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WeatherError::InvalidRequest(message) | WeatherError::DateNotCovered(message) => write!(f, "{}", message),
+            _ => {
+                let failures : Vec<String> = self.failures()
+                    .iter()
+                    .map(|failure| format!("{}: {}", failure.provider, failure.detail))
+                    .collect();
+                if failures.is_empty() {
+                    write!(f, "{}", self.title())
+                } else {
+                    write!(f, "{} ({})", self.title(), failures.join(", "))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    failures: &'a [ProviderFailure]
+}
+
+impl ResponseError for WeatherError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WeatherError::InvalidRequest(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            WeatherError::UnknownLocation(_) | WeatherError::DateNotCovered(_) => StatusCode::NOT_FOUND,
+            WeatherError::InvalidUpstreamKey(_) | WeatherError::UpstreamParse(_) => StatusCode::BAD_GATEWAY,
+            WeatherError::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            WeatherError::AllProvidersDown(_) => StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = Problem {
+            problem_type: format!("urn:weather-reports:problem:{}", self.problem_type()),
+            title: self.title(),
+            status: status.as_u16(),
+            detail: self.to_string(),
+            failures: self.failures()
+        };
+
+        HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .body(serde_json::to_string(&problem).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(provider: &'static str, error: ProviderError) -> ProviderFailure {
+        ProviderFailure::new(provider, &error)
+    }
+
+    #[test]
+    fn it_classifies_provider_failures() {
+        let error = WeatherError::from_failures(vec![
+            failure("open_weather", ProviderError::UnknownLocation),
+            failure("weatherbit", ProviderError::UnsupportedLocation(Location::Coordinates { lat: 1.0, lon: 2.0 }))
+        ]);
+        assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
+
+        let error = WeatherError::from_failures(vec![
+            failure("open_weather", ProviderError::Timeout),
+            failure("weatherbit", ProviderError::Timeout)
+        ]);
+        assert_eq!(error.status_code(), StatusCode::GATEWAY_TIMEOUT);
+
+        let error = WeatherError::from_failures(vec![
+            failure("open_weather", ProviderError::InvalidApiKey(None)),
+            failure("weatherbit", ProviderError::InvalidApiKey(None))
+        ]);
+        assert_eq!(error.status_code(), StatusCode::BAD_GATEWAY);
+
+        let error = WeatherError::from_failures(vec![
+            failure("open_weather", ProviderError::Parse("missing temp".to_string()))
+        ]);
+        assert_eq!(error.status_code(), StatusCode::BAD_GATEWAY);
+
+        let error = WeatherError::from_failures(vec![
+            failure("open_weather", ProviderError::Timeout),
+            failure("weatherbit", ProviderError::InvalidApiKey(None))
+        ]);
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+        assert_eq!(WeatherError::from_failures(vec![]).status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn it_renders_problem_json() {
+        let error = WeatherError::from_failures(vec![
+            failure("open_weather", ProviderError::Timeout),
+            failure("weatherbit", ProviderError::Unavailable("503 Service Unavailable".to_string()))
+        ]);
+
+        let response = error.error_response();
+        let body = match response.body().as_ref() {
+            Some(actix_web::body::Body::Bytes(bytes)) => serde_json::from_slice::<serde_json::Value>(bytes).unwrap(),
+            _ => panic!("expected bytes body")
+        };
+
+        assert_eq!(response.headers().get("content-type").unwrap(), "application/problem+json");
+        assert_eq!(body["status"], 503);
+        assert_eq!(body["type"], "urn:weather-reports:problem:all-providers-down");
+        assert_eq!(body["failures"][0]["provider"], "open_weather");
+        assert_eq!(body["failures"][0]["error"], "timeout");
+        assert_eq!(body["failures"][1]["detail"], "503 Service Unavailable");
+    }
+
+    #[actix_rt::test]
+    async fn it_does_not_leak_api_key_of_failed_request() {
+        let error = reqwest::Client::new().get("http://127.0.0.1:1/weather?APPID=secret-key").send().await.unwrap_err();
+        let error = WeatherError::from_failures(vec![failure("open_weather", ProviderError::from(error))]);
+
+        let response = error.error_response();
+        let body = match response.body().as_ref() {
+            Some(actix_web::body::Body::Bytes(bytes)) => String::from_utf8(bytes.to_vec()).unwrap(),
+            _ => panic!("expected bytes body")
+        };
+
+        assert!(body.contains("could not connect to provider"));
+        assert!(!body.contains("secret-key"));
+    }
+}
@@ -1,73 +1,383 @@
-use actix_web::{web, get, HttpResponse, Responder};
+mod json;
+
+use actix_web::{web, get, HttpRequest, HttpResponse};
+use actix_web::http::header::ACCEPT;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
 use serde::Deserialize;
-use crate::WeatherReport;
+use crate::{AggregatedReport, Confidence, Config, Location, WeatherReport};
+use crate::errors::WeatherError;
+use crate::location::LocationParams;
+use crate::units::{UnitLabels, UnitSystem};
 use crate::weather_aggregator;
-use chrono::NaiveDateTime;
+use crate::weather_aggregator::{Aggregation, ProviderRegistry};
 
 #[derive(Deserialize)]
 pub struct DailyParams {
-    city_name: Option<String>,
-    days_since: Option<String>
+    #[serde(flatten)]
+    location: LocationParams,
+    date: Option<String>,
+    days_since: Option<String>,
+    format: Option<String>,
+    units: Option<String>,
+    aggregate: Option<String>
 }
 
+enum RequestedDay {
+    Current,
+    DaysAhead(usize),
+    Date(NaiveDate)
+}
 
 #[get("/daily")]
-async fn daily(web::Query(params): web::Query<DailyParams>) -> impl Responder {
-    let mut days_since: Option<usize> = None;
-    if let Some(days_since_str) = &params.days_since {
-        days_since = days_since_str.parse().ok();
+async fn daily(request: HttpRequest, registry: web::Data<ProviderRegistry>, config: web::Data<Config>, aggregation: web::Data<Aggregation>, web::Query(params): web::Query<DailyParams>) -> Result<HttpResponse, WeatherError> {
+    let location = Location::from_params(&params.location).map_err(WeatherError::InvalidRequest)?;
+    let units = UnitSystem::from_param(&params.units).map_err(WeatherError::InvalidRequest)?;
+    let aggregation = requested_aggregation(&aggregation, &params.aggregate)?;
+    let horizon_days = config.forecast_horizon_days.min(registry.max_forecast_days());
+
+    let report = match requested_day(&params, horizon_days)? {
+        RequestedDay::Current => weather_aggregator::get_current_weather(&registry, &location, &aggregation).await?,
+        RequestedDay::DaysAhead(days_since) => weather_aggregator::get_specific_day_weather(&registry, &location, days_since, &aggregation).await?,
+        RequestedDay::Date(date) => {
+            let today = Utc::today().naive_utc();
+            weather_aggregator::get_weather_on_date(&registry, &location, date, today, horizon_days, &aggregation).await?
+        }
     };
-    if params.city_name.is_none() {
-        HttpResponse::UnprocessableEntity().body("city_name should be specified")
-    } else if params.days_since.is_some() && (days_since.is_none() || days_since.unwrap() > 6) {
-        HttpResponse::UnprocessableEntity().body("days_since should be non-negative number, not higher than 6")
+
+    let report = in_units(report, units);
+
+    if wants_json(&request, &params.format) {
+        Ok(HttpResponse::Ok().json(json::DailyResponse::new(&report, units)))
     } else {
-        let report : Result<WeatherReport, String>;
-        if days_since.is_none() {
-            report = weather_aggregator::get_current_weather(params.city_name.unwrap().as_str()).await;
-        } else {
-            report = weather_aggregator::get_specific_day_weather(params.city_name.unwrap().as_str(), days_since.unwrap()).await;
-        };
-
-        if report.is_ok() {
-            HttpResponse::Ok().body(format_daily_report(report.unwrap()))
-        } else {
-            HttpResponse::NotFound().body(report.err().unwrap())
-        }
+        Ok(HttpResponse::Ok().body(format_daily_report(report, &units.labels())))
+    }
+}
+
+// relative keywords are resolved against location's local date, so they don't depend on server timezone
+fn requested_day(params: &DailyParams, horizon_days: usize) -> Result<RequestedDay, WeatherError> {
+    let requested = match (&params.date, &params.days_since) {
+        (Some(_), Some(_)) => Err(WeatherError::InvalidRequest(String::from("specify either date or days_since, not both"))),
+        (None, Some(_)) if horizon_days == 0 => Err(forecast_unavailable()),
+        (Some(date), None) => match date.to_lowercase().as_str() {
+            "today" => Ok(RequestedDay::Current),
+            "tomorrow" => Ok(RequestedDay::DaysAhead(1)),
+            date => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(RequestedDay::Date)
+                .map_err(|_| WeatherError::InvalidRequest(String::from("date should be YYYY-MM-DD, today or tomorrow")))
+        },
+        (None, Some(days_since)) => days_since
+            .parse()
+            .ok()
+            .filter(|days_since| *days_since < horizon_days)
+            .map(RequestedDay::DaysAhead)
+            .ok_or_else(|| WeatherError::InvalidRequest(format!(
+                "days_since should be non-negative number, not higher than {}", horizon_days.saturating_sub(1)
+            ))),
+        (None, None) => Ok(RequestedDay::Current)
+    }?;
+
+    match requested {
+        RequestedDay::Current => Ok(requested),
+        _ if horizon_days == 0 => Err(forecast_unavailable()),
+        _ => Ok(requested)
     }
 }
 
+// custom providers may serve only current weather, leaving no provider to forecast with
+fn forecast_unavailable() -> WeatherError {
+    WeatherError::InvalidRequest(String::from("forecasts are not available, none of active providers can forecast"))
+}
+
 #[derive(Deserialize)]
 pub struct ForecastParams {
-    city_name: Option<String>,
+    #[serde(flatten)]
+    location: LocationParams,
+    days: Option<String>,
+    format: Option<String>,
+    units: Option<String>,
+    aggregate: Option<String>
 }
 
-const FORECAST_DAYS : usize = 5;
+const DEFAULT_FORECAST_DAYS : usize = 5;
 
 #[get("/forecast")]
-async fn forecast(web::Query(params): web::Query<ForecastParams>) -> impl Responder {
-    if params.city_name.is_none() {
-        HttpResponse::UnprocessableEntity().body("city_name should be specified")
+async fn forecast(request: HttpRequest, registry: web::Data<ProviderRegistry>, config: web::Data<Config>, aggregation: web::Data<Aggregation>, web::Query(params): web::Query<ForecastParams>) -> Result<HttpResponse, WeatherError> {
+    let location = Location::from_params(&params.location).map_err(WeatherError::InvalidRequest)?;
+    let units = UnitSystem::from_param(&params.units).map_err(WeatherError::InvalidRequest)?;
+    let aggregation = requested_aggregation(&aggregation, &params.aggregate)?;
+    let horizon_days = config.forecast_horizon_days.min(registry.max_forecast_days());
+    if horizon_days == 0 {
+        return Err(forecast_unavailable());
+    }
+    let days_count = match &params.days {
+        None => DEFAULT_FORECAST_DAYS.min(horizon_days),
+        Some(days) => days
+            .parse()
+            .ok()
+            .filter(|days| (1..=horizon_days).contains(days))
+            .ok_or_else(|| WeatherError::InvalidRequest(format!("days should be a number within 1..{}", horizon_days)))?
+    };
+    let report = weather_aggregator::get_forecast_weather(&registry, &location, days_count, &aggregation).await?;
+
+    let report : Vec<AggregatedReport> = report.into_iter().map(|day| in_units(day, units)).collect();
+
+    if wants_json(&request, &params.format) {
+        Ok(HttpResponse::Ok().json(json::ForecastResponse::new(&report, units)))
     } else {
-        let report = weather_aggregator::get_forecast_weather(params.city_name.unwrap().as_str(), FORECAST_DAYS).await;
+        Ok(HttpResponse::Ok().body(format_forecast_report(report, &units.labels())))
+    }
+}
 
-        if report.is_ok() {
-            HttpResponse::Ok().body(format_forecast_report(report.unwrap()))
-        } else {
-            HttpResponse::NotFound().body(report.err().unwrap())
-        }
+#[derive(Deserialize)]
+pub struct HourlyParams {
+    #[serde(flatten)]
+    location: LocationParams,
+    hours: Option<String>,
+    format: Option<String>,
+    units: Option<String>,
+    aggregate: Option<String>
+}
+
+const DEFAULT_HOURS : usize = 24;
+const MAX_HOURS : usize = 48;
+
+#[get("/hourly")]
+async fn hourly(request: HttpRequest, registry: web::Data<ProviderRegistry>, aggregation: web::Data<Aggregation>, web::Query(params): web::Query<HourlyParams>) -> Result<HttpResponse, WeatherError> {
+    let location = Location::from_params(&params.location).map_err(WeatherError::InvalidRequest)?;
+    let units = UnitSystem::from_param(&params.units).map_err(WeatherError::InvalidRequest)?;
+    let aggregation = requested_aggregation(&aggregation, &params.aggregate)?;
+    let hours_count = match &params.hours {
+        None => DEFAULT_HOURS,
+        Some(hours) => hours
+            .parse()
+            .ok()
+            .filter(|hours| (1..=MAX_HOURS).contains(hours))
+            .ok_or_else(|| WeatherError::InvalidRequest(format!("hours should be a number within 1..{}", MAX_HOURS)))?
+    };
+    let report = weather_aggregator::get_hourly_weather(&registry, &location, hours_count, &aggregation).await?;
+
+    let report : Vec<AggregatedReport> = report.into_iter().map(|hour| in_units(hour, units)).collect();
+
+    if wants_json(&request, &params.format) {
+        Ok(HttpResponse::Ok().json(json::HourlyResponse::new(&report, units)))
+    } else {
+        Ok(HttpResponse::Ok().body(format_hourly_report(report, &units.labels())))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    #[serde(flatten)]
+    location: LocationParams,
+    date: Option<String>,
+    format: Option<String>,
+    units: Option<String>,
+    aggregate: Option<String>
+}
+
+#[get("/history")]
+async fn history(request: HttpRequest, registry: web::Data<ProviderRegistry>, aggregation: web::Data<Aggregation>, web::Query(params): web::Query<HistoryParams>) -> Result<HttpResponse, WeatherError> {
+    let location = Location::from_params(&params.location).map_err(WeatherError::InvalidRequest)?;
+    let units = UnitSystem::from_param(&params.units).map_err(WeatherError::InvalidRequest)?;
+    let aggregation = requested_aggregation(&aggregation, &params.aggregate)?;
+    let date = params.date
+        .as_ref()
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+        .ok_or_else(|| WeatherError::InvalidRequest(String::from("date should be specified as YYYY-MM-DD")))?;
+    let today = Utc::today().naive_utc();
+    let report = weather_aggregator::get_history_weather(&registry, &location, date, today, &aggregation).await?;
+
+    let report = in_units(report, units);
+
+    if wants_json(&request, &params.format) {
+        Ok(HttpResponse::Ok().json(json::DailyResponse::new(&report, units)))
+    } else {
+        Ok(HttpResponse::Ok().body(format_daily_report(report, &units.labels())))
+    }
+}
+
+#[get("/providers")]
+async fn providers(registry: web::Data<ProviderRegistry>, config: web::Data<Config>) -> HttpResponse {
+    HttpResponse::Ok().json(json::ProvidersResponse::new(&registry, &config))
+}
+
+#[get("/admin/circuit_breakers")]
+async fn circuit_breakers(registry: web::Data<ProviderRegistry>) -> HttpResponse {
+    HttpResponse::Ok().json(json::CircuitBreakersResponse::new(&registry))
+}
+
+// ?aggregate= applies a single strategy to every field instead of the configured ones
+fn requested_aggregation(configured: &Aggregation, aggregate: &Option<String>) -> Result<Aggregation, WeatherError> {
+    match aggregate {
+        Some(strategy) => strategy
+            .parse()
+            .map(|strategy| configured.clone().with_strategy(strategy))
+            .map_err(WeatherError::InvalidRequest),
+        None => Ok(configured.clone())
+    }
+}
+
+fn in_units(aggregated: AggregatedReport, units: UnitSystem) -> AggregatedReport {
+    AggregatedReport {
+        report: units.convert(aggregated.report),
+        spread: units.convert_spread(aggregated.spread),
+        ..aggregated
+    }
+}
+
+// explicit ?format= wins over Accept header, plain text is the default for curl users
+fn wants_json(request: &HttpRequest, format: &Option<String>) -> bool {
+    match format.as_deref() {
+        Some(format) => format.eq_ignore_ascii_case("json"),
+        None => request
+            .headers()
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json"))
+    }
+}
+
+fn format_forecast_report(days: Vec<AggregatedReport>, labels: &UnitLabels) -> String {
+    let mut result_as_string = String::from("");
+    for day in days {
+        result_as_string = format!("{}{}\n", result_as_string, format_forecast_day(day, labels))
     }
+    result_as_string
 }
 
-fn format_forecast_report(reports: Vec<WeatherReport>) -> String {
+fn format_forecast_day(day: AggregatedReport, labels: &UnitLabels) -> String {
+    format!("{}, temperature: {}{}, providers: {}{}",
+            day.date.format("%a %b %e"), day.report.temperature, format_report_details(&day.report, labels),
+            day.providers.join(", "), format_confidence(&day))
+}
+
+fn format_hourly_report(hours: Vec<AggregatedReport>, labels: &UnitLabels) -> String {
     let mut result_as_string = String::from("");
-    for report in reports {
-        result_as_string = format!("{}{}\n", result_as_string, format_daily_report(report))
+    for hour in hours {
+        let local_time = NaiveDateTime::from_timestamp(hour.report.unix_timestamp + hour.report.utc_offset as i64, 0);
+        result_as_string = format!("{}{}, temperature: {}{}, providers: {}{}\n",
+                                   result_as_string, local_time.format("%a %b %e %H:%M"), hour.report.temperature,
+                                   format_report_details(&hour.report, labels), hour.providers.join(", "), format_confidence(&hour));
     }
     result_as_string
 }
 
-fn format_daily_report(report: WeatherReport) -> String {
-    let date = NaiveDateTime::from_timestamp(report.unix_timestamp, 0);
-    format!("{}, temperature: {}", date.format("%a %b %e").to_string(), report.temperature)
+fn format_daily_report(day: AggregatedReport, labels: &UnitLabels) -> String {
+    format!("{}, temperature: {}{}{}",
+            day.date.format("%a %b %e"), day.report.temperature, format_report_details(&day.report, labels), format_confidence(&day))
+}
+
+// agreeing providers are the normal case, so only doubtful values are marked
+fn format_confidence(aggregated: &AggregatedReport) -> String {
+    if aggregated.is_single_source() {
+        String::from(" (single source)")
+    } else if aggregated.confidence != Confidence::High {
+        format!(" ({} confidence)", aggregated.confidence)
+    } else {
+        String::from("")
+    }
+}
+
+fn format_report_details(report: &WeatherReport, labels: &UnitLabels) -> String {
+    let mut details = String::from("");
+    if let (Some(temperature_min), Some(temperature_max)) = (report.temperature_min, report.temperature_max) {
+        details = format!("{}, low: {}, high: {}", details, temperature_min, temperature_max);
+    }
+    if let Some(temperature_morning) = report.temperature_morning {
+        details = format!("{}, morning: {}", details, temperature_morning);
+    }
+    if let Some(temperature_evening) = report.temperature_evening {
+        details = format!("{}, evening: {}", details, temperature_evening);
+    }
+    if let Some(temperature_night) = report.temperature_night {
+        details = format!("{}, night: {}", details, temperature_night);
+    }
+    if let Some(humidity) = report.humidity {
+        details = format!("{}, humidity: {}%", details, humidity);
+    }
+    if let Some(wind_speed) = report.wind_speed {
+        details = format!("{}, wind: {} {}", details, wind_speed, labels.wind_speed);
+        if let Some(wind_direction) = report.wind_direction {
+            details = format!("{} from {}°", details, wind_direction.round());
+        }
+    }
+    if let Some(pressure) = report.pressure {
+        details = format!("{}, pressure: {} {}", details, pressure, labels.pressure);
+    }
+    if let Some(cloud_cover) = report.cloud_cover {
+        details = format!("{}, clouds: {}%", details, cloud_cover);
+    }
+    if let Some(precipitation) = report.precipitation {
+        details = format!("{}, precipitation: {} {}", details, precipitation, labels.precipitation);
+    }
+    if let Some(condition) = &report.condition {
+        details = format!("{}, {}", details, condition.description.to_lowercase());
+    }
+    details
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn it_reads_location_from_query() {
+        let web::Query(params) = web::Query::<DailyParams>::from_query("lat=48.85&lon=2.35&days_since=1").unwrap();
+
+        assert_eq!(Location::from_params(&params.location).unwrap(), Location::Coordinates { lat: 48.85, lon: 2.35 });
+        assert_eq!(params.days_since, Some("1".to_string()));
+    }
+
+    #[test]
+    fn it_negotiates_json_format() {
+        let plain_request = TestRequest::default().to_http_request();
+        let json_request = TestRequest::default().header(ACCEPT, "text/html, application/json;q=0.9").to_http_request();
+
+        assert!(!wants_json(&plain_request, &None));
+        assert!(wants_json(&plain_request, &Some("json".to_string())));
+        assert!(wants_json(&json_request, &None));
+        assert!(!wants_json(&json_request, &Some("text".to_string())));
+    }
+
+    fn daily_params(query: &str) -> DailyParams {
+        web::Query::<DailyParams>::from_query(query).unwrap().into_inner()
+    }
+
+    #[test]
+    fn it_selects_requested_day() {
+        assert!(matches!(requested_day(&daily_params("city_name=kazan"), 7), Ok(RequestedDay::Current)));
+        assert!(matches!(requested_day(&daily_params("city_name=kazan&date=today"), 7), Ok(RequestedDay::Current)));
+        assert!(matches!(requested_day(&daily_params("city_name=kazan&date=Tomorrow"), 7), Ok(RequestedDay::DaysAhead(1))));
+        assert!(matches!(requested_day(&daily_params("city_name=kazan&days_since=6"), 7), Ok(RequestedDay::DaysAhead(6))));
+        assert!(matches!(
+            requested_day(&daily_params("city_name=kazan&date=2026-10-20"), 7),
+            Ok(RequestedDay::Date(date)) if date == NaiveDate::from_ymd(2026, 10, 20)
+        ));
+
+        assert!(requested_day(&daily_params("city_name=kazan&days_since=7"), 7).is_err());
+        assert!(requested_day(&daily_params("city_name=kazan&date=20.10.2026"), 7).is_err());
+        assert!(requested_day(&daily_params("city_name=kazan&date=today&days_since=1"), 7).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_forecasts_when_no_provider_can_forecast() {
+        let mut app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(ProviderRegistry::new()))
+                .app_data(web::Data::new(Config::default()))
+                .app_data(web::Data::new(Aggregation::default()))
+                .service(daily)
+                .service(forecast)
+        ).await;
+
+        for uri in &["/forecast?city_name=kazan", "/daily?city_name=kazan&date=tomorrow", "/daily?city_name=kazan&days_since=1"] {
+            let response = actix_web::test::call_service(&mut app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), actix_web::http::StatusCode::UNPROCESSABLE_ENTITY);
+
+            let body : serde_json::Value = actix_web::test::read_body_json(response).await;
+            assert_eq!(body["type"], "urn:weather-reports:problem:invalid-request");
+            assert_eq!(body["detail"], "forecasts are not available, none of active providers can forecast");
+        }
+    }
 }
@@ -0,0 +1,340 @@
+use crate::{AggregatedReport, Confidence, Config, ReportSpread, WeatherCondition, WeatherReport};
+use crate::units::UnitSystem;
+use crate::weather_aggregator::{Capabilities, CircuitStatus, ProviderRegistry};
+use chrono::{FixedOffset, TimeZone};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct DailyResponse<'a> {
+    #[serde(flatten)]
+    pub day: DayJson<'a>,
+    pub units: Units
+}
+
+#[derive(Serialize)]
+pub struct ForecastResponse<'a> {
+    pub units: Units,
+    pub days: Vec<DayJson<'a>>
+}
+
+#[derive(Serialize)]
+pub struct HourlyResponse<'a> {
+    pub units: Units,
+    pub hours: Vec<HourJson<'a>>
+}
+
+#[derive(Serialize)]
+pub struct HourJson<'a> {
+    pub time: String,
+    pub timestamp: i64,
+    pub utc_offset: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<&'a str>,
+    pub providers: &'a [&'static str],
+    pub single_source: bool,
+    pub values: Values<'a>,
+    pub spread: &'a ReportSpread,
+    pub confidence: Confidence
+}
+
+#[derive(Serialize)]
+pub struct DayJson<'a> {
+    pub date: String,
+    pub timestamp: i64,
+    pub utc_offset: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<&'a str>,
+    pub providers: &'a [&'static str],
+    pub single_source: bool,
+    pub values: Values<'a>,
+    pub spread: &'a ReportSpread,
+    pub confidence: Confidence
+}
+
+#[derive(Serialize)]
+pub struct Values<'a> {
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_morning: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_evening: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_night: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_speed: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_direction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_cover: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precipitation: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<&'a WeatherCondition>
+}
+
+#[derive(Serialize)]
+pub struct Units {
+    pub temperature: &'static str,
+    pub humidity: &'static str,
+    pub wind_speed: &'static str,
+    pub wind_direction: &'static str,
+    pub pressure: &'static str,
+    pub cloud_cover: &'static str,
+    pub precipitation: &'static str
+}
+
+impl Units {
+    pub fn for_system(units: UnitSystem) -> Self {
+        let labels = units.labels();
+        Self {
+            temperature: labels.temperature,
+            humidity: "percent",
+            wind_speed: labels.wind_speed,
+            wind_direction: "degrees",
+            pressure: labels.pressure,
+            cloud_cover: "percent",
+            precipitation: labels.precipitation
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ProvidersResponse {
+    pub active: Vec<ActiveProvider>,
+    pub inactive: Vec<InactiveProvider>
+}
+
+#[derive(Serialize)]
+pub struct ActiveProvider {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub capabilities: Capabilities
+}
+
+#[derive(Serialize)]
+pub struct InactiveProvider {
+    pub name: String,
+    pub reason: &'static str
+}
+
+#[derive(Serialize)]
+pub struct CircuitBreakersResponse {
+    pub providers: Vec<ProviderCircuit>
+}
+
+#[derive(Serialize)]
+pub struct ProviderCircuit {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub status: CircuitStatus
+}
+
+impl<'a> DailyResponse<'a> {
+    pub fn new(day: &'a AggregatedReport, units: UnitSystem) -> Self {
+        Self { day: DayJson::new(day), units: Units::for_system(units) }
+    }
+}
+
+impl<'a> ForecastResponse<'a> {
+    pub fn new(days: &'a [AggregatedReport], units: UnitSystem) -> Self {
+        Self { units: Units::for_system(units), days: days.iter().map(DayJson::new).collect() }
+    }
+}
+
+impl<'a> HourlyResponse<'a> {
+    pub fn new(hours: &'a [AggregatedReport], units: UnitSystem) -> Self {
+        Self { units: Units::for_system(units), hours: hours.iter().map(HourJson::new).collect() }
+    }
+}
+
+impl ProvidersResponse {
+    pub fn new(registry: &ProviderRegistry, config: &Config) -> Self {
+        let active = registry
+            .providers()
+            .map(|provider| ActiveProvider { name: provider.name(), capabilities: provider.capabilities() })
+            .collect();
+        let inactive = config
+            .inactive_providers()
+            .into_iter()
+            .map(|(name, reason)| InactiveProvider { name: name.to_string(), reason })
+            .collect();
+        Self { active, inactive }
+    }
+}
+
+impl CircuitBreakersResponse {
+    pub fn new(registry: &ProviderRegistry) -> Self {
+        let providers = registry
+            .providers()
+            .filter_map(|provider| Some(ProviderCircuit { name: provider.name(), status: provider.circuit_status()? }))
+            .collect();
+        Self { providers }
+    }
+}
+
+impl<'a> DayJson<'a> {
+    fn new(day: &'a AggregatedReport) -> Self {
+        Self {
+            date: day.date.format("%Y-%m-%d").to_string(),
+            timestamp: day.report.unix_timestamp,
+            utc_offset: FixedOffset::east(day.report.utc_offset).to_string(),
+            timezone: day.report.timezone.as_deref(),
+            providers: &day.providers,
+            single_source: day.is_single_source(),
+            values: Values::new(&day.report),
+            spread: &day.spread,
+            confidence: day.confidence
+        }
+    }
+}
+
+impl<'a> HourJson<'a> {
+    // local time of the location, with utc offset so it can be parsed unambiguously
+    fn new(hour: &'a AggregatedReport) -> Self {
+        let offset = FixedOffset::east(hour.report.utc_offset);
+        let time = offset.timestamp(hour.report.unix_timestamp, 0);
+        Self {
+            time: time.to_rfc3339(),
+            timestamp: hour.report.unix_timestamp,
+            utc_offset: offset.to_string(),
+            timezone: hour.report.timezone.as_deref(),
+            providers: &hour.providers,
+            single_source: hour.is_single_source(),
+            values: Values::new(&hour.report),
+            spread: &hour.spread,
+            confidence: hour.confidence
+        }
+    }
+}
+
+impl<'a> Values<'a> {
+    fn new(report: &'a WeatherReport) -> Self {
+        Self {
+            temperature: report.temperature,
+            temperature_min: report.temperature_min,
+            temperature_max: report.temperature_max,
+            temperature_morning: report.temperature_morning,
+            temperature_evening: report.temperature_evening,
+            temperature_night: report.temperature_night,
+            humidity: report.humidity,
+            wind_speed: report.wind_speed,
+            wind_direction: report.wind_direction,
+            pressure: report.pressure,
+            cloud_cover: report.cloud_cover,
+            precipitation: report.precipitation,
+            condition: report.condition.as_ref()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Spread;
+    use chrono::NaiveDate;
+    use serde_json::json;
+
+    fn aggregated_report() -> AggregatedReport {
+        AggregatedReport {
+            date: NaiveDate::from_ymd(2021, 2, 23),
+            report: WeatherReport {
+                temperature: -17.66,
+                unix_timestamp: 1614078000,
+                utc_offset: 10800,
+                timezone: Some("Europe/Moscow".to_string()),
+                humidity: Some(73.0),
+                condition: Some(WeatherCondition { code: 800, description: "clear sky".to_string() }),
+                ..Default::default()
+            },
+            providers: vec!["open_weather", "weatherbit"],
+            spread: ReportSpread {
+                temperature: Some(Spread { min: -18.5, max: -16.82, std_dev: 0.84, provider_count: 2 }),
+                ..Default::default()
+            },
+            confidence: Confidence::High
+        }
+    }
+
+    #[test]
+    fn it_serializes_daily_response() {
+        let day = aggregated_report();
+
+        let json = serde_json::to_value(DailyResponse::new(&day, UnitSystem::Metric)).unwrap();
+
+        assert_eq!(json["date"], json!("2021-02-23"));
+        assert_eq!(json["timestamp"], json!(1614078000));
+        assert_eq!(json["utc_offset"], json!("+03:00"));
+        assert_eq!(json["timezone"], json!("Europe/Moscow"));
+        assert_eq!(json["providers"], json!(["open_weather", "weatherbit"]));
+        assert_eq!(json["values"], json!({
+            "temperature": -17.66,
+            "humidity": 73.0,
+            "condition": { "code": 800, "description": "clear sky" }
+        }));
+        assert_eq!(json["spread"], json!({
+            "temperature": { "min": -18.5, "max": -16.82, "std_dev": 0.84, "provider_count": 2 }
+        }));
+        assert_eq!(json["confidence"], json!("high"));
+        assert_eq!(json["units"]["temperature"], json!("celsius"));
+    }
+
+    #[test]
+    fn it_serializes_forecast_response() {
+        let days = vec![aggregated_report(), aggregated_report()];
+
+        let json = serde_json::to_value(ForecastResponse::new(&days, UnitSystem::Metric)).unwrap();
+
+        assert_eq!(json["days"].as_array().unwrap().len(), 2);
+        assert_eq!(json["days"][0]["single_source"], json!(false));
+        assert_eq!(json["days"][1]["values"]["temperature"], json!(-17.66));
+        assert_eq!(json["units"]["wind_speed"], json!("m/s"));
+
+        let json = serde_json::to_value(ForecastResponse::new(&days, UnitSystem::Imperial)).unwrap();
+
+        assert_eq!(json["units"]["temperature"], json!("fahrenheit"));
+        assert_eq!(json["units"]["wind_speed"], json!("mph"));
+        assert_eq!(json["units"]["pressure"], json!("inHg"));
+        assert_eq!(json["units"]["precipitation"], json!("in"));
+    }
+
+    #[test]
+    fn it_serializes_hourly_response() {
+        let json = serde_json::to_value(HourlyResponse::new(&[aggregated_report()], UnitSystem::Metric)).unwrap();
+
+        assert_eq!(json["hours"][0]["time"], json!("2021-02-23T14:00:00+03:00"));
+        assert_eq!(json["hours"][0]["timestamp"], json!(1614078000));
+        assert_eq!(json["hours"][0]["single_source"], json!(false));
+        assert_eq!(json["hours"][0]["values"]["temperature"], json!(-17.66));
+    }
+
+    #[test]
+    fn it_serializes_providers_response() {
+        let mut config = Config::default();
+        config.apply_env(|name| match name {
+            "WEATHERBIT_API_KEY" => Some("key".to_string()),
+            "OPEN_METEO_ENABLED" | "NWS_ENABLED" | "MET_NORWAY_ENABLED" => Some("false".to_string()),
+            _ => None
+        });
+        let registry = crate::weather_aggregator::registry_from_config(&config);
+
+        let json = serde_json::to_value(ProvidersResponse::new(&registry, &config)).unwrap();
+
+        assert_eq!(json["active"], json!([
+            { "name": "weatherbit", "current": true, "forecast": true, "max_forecast_days": 16, "hourly": true, "max_forecast_hours": 48, "history_days": 365 }
+        ]));
+        assert_eq!(json["inactive"], json!([
+            { "name": "open_weather", "reason": "api key is not configured" },
+            { "name": "open_meteo", "reason": "disabled" },
+            { "name": "nws", "reason": "disabled" },
+            { "name": "met_norway", "reason": "disabled" }
+        ]));
+    }
+}
@@ -1,34 +1,149 @@
-use actix_web::{App, HttpServer};
+use actix_web::{web, App, HttpServer};
 use actix_web::dev::Server;
-use dotenv::dotenv;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::fmt;
 use std::net::TcpListener;
-use std::env;
 
 mod weather_aggregator;
 mod handlers;
+mod location;
+mod errors;
+mod config;
+mod units;
 
-#[derive(Debug)]
+pub use location::Location;
+pub use config::{Config, ConfigError};
+
+#[derive(Debug, Clone, Default)]
 pub struct WeatherReport {
     pub temperature: f64,
-    pub unix_timestamp: i64
+    pub unix_timestamp: i64,
+    pub utc_offset: i32,
+    pub timezone: Option<String>,
+    pub temperature_min: Option<f64>,
+    pub temperature_max: Option<f64>,
+    pub temperature_morning: Option<f64>,
+    pub temperature_evening: Option<f64>,
+    pub temperature_night: Option<f64>,
+    pub humidity: Option<f64>,
+    pub wind_speed: Option<f64>,
+    pub wind_direction: Option<f64>,
+    pub pressure: Option<f64>,
+    pub cloud_cover: Option<f64>,
+    pub precipitation: Option<f64>,
+    pub condition: Option<WeatherCondition>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WeatherCondition {
+    pub code: u32,
+    pub description: String
+}
+
+impl WeatherReport {
+    pub fn local_date(&self) -> NaiveDate {
+        NaiveDateTime::from_timestamp(self.unix_timestamp + self.utc_offset as i64, 0).date()
+    }
+
+    pub fn local_day_start(&self) -> i64 {
+        self.local_date().and_hms(0, 0, 0).timestamp() - self.utc_offset as i64
+    }
+}
+
+#[derive(Debug)]
+pub struct AggregatedReport {
+    pub date: NaiveDate,
+    pub report: WeatherReport,
+    pub providers: Vec<&'static str>,
+    pub spread: ReportSpread,
+    pub confidence: Confidence
 }
 
-pub fn run(listener: TcpListener) -> Result<Server, std::io::Error> {
-    dotenv().ok();
-    verify_env_vars();
+// how far providers' values for a single field are from each other
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Spread {
+    pub min: f64,
+    pub max: f64,
+    pub std_dev: f64,
+    pub provider_count: u64
+}
 
-    let server = HttpServer::new(|| {
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReportSpread {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_min: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_max: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_morning: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_evening: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_night: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_speed: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_cover: Option<Spread>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precipitation: Option<Spread>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    High,
+    Medium,
+    Low
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Confidence::High => write!(f, "high"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::Low => write!(f, "low")
+        }
+    }
+}
+
+impl AggregatedReport {
+    pub fn is_single_source(&self) -> bool {
+        self.providers.len() == 1
+    }
+}
+
+pub fn run(listener: TcpListener, config: Config) -> Result<Server, std::io::Error> {
+    let registry = weather_aggregator::registry_from_config(&config);
+    log::info!("Active weather providers: {}", registry.names().join(", "));
+    for (name, reason) in config.inactive_providers() {
+        log::warn!("Skipping {} provider: {}", name, reason);
+    }
+
+    let aggregation = web::Data::new(weather_aggregator::Aggregation::from_config(&config));
+    let registry = web::Data::new(registry);
+    let config = web::Data::new(config);
+
+    let server = HttpServer::new(move || {
         App::new()
+            .app_data(registry.clone())
+            .app_data(config.clone())
+            .app_data(aggregation.clone())
             .service(handlers::daily)
             .service(handlers::forecast)
+            .service(handlers::hourly)
+            .service(handlers::history)
+            .service(handlers::providers)
+            .service(handlers::circuit_breakers)
     })
     .listen(listener)?
     .run();
 
     Ok(server)
 }
-
-fn verify_env_vars() {
-    env::var("OPEN_WEATHER_APPID").expect("OPEN_WEATHER_APPID is not specified");
-    env::var("WEATHERBIT_API_KEY").expect("WEATHERBIT_API_KEY is not specified");
-}
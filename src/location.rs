@@ -0,0 +1,147 @@
+use serde::Deserialize;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    Coordinates { lat: f64, lon: f64 },
+    PostalCode { zip: String, country: String },
+    City { name: String, country_code: Option<String> },
+    ProviderCityId { provider: String, id: String }
+}
+
+#[derive(Deserialize, Default)]
+pub struct LocationParams {
+    city_name: Option<String>,
+    country_code: Option<String>,
+    lat: Option<String>,
+    lon: Option<String>,
+    zip: Option<String>,
+    country: Option<String>,
+    city_id: Option<String>
+}
+
+impl Location {
+    pub fn from_params(params: &LocationParams) -> Result<Location, String> {
+        if params.lat.is_some() || params.lon.is_some() {
+            Self::coordinates_from_params(params)
+        } else if let Some(zip) = &params.zip {
+            match &params.country {
+                Some(country) => Ok(Location::PostalCode { zip: zip.clone(), country: country.clone() }),
+                None => Err(String::from("country should be specified along with zip"))
+            }
+        } else if let Some(city_id) = &params.city_id {
+            match city_id.split_once(':') {
+                Some((provider, id)) if !provider.is_empty() && !id.is_empty() =>
+                    Ok(Location::ProviderCityId { provider: provider.to_string(), id: id.to_string() }),
+                _ => Err(String::from("city_id should look like <provider>:<id>, e.g. open_weather:551487"))
+            }
+        } else if let Some(city_name) = &params.city_name {
+            Ok(Location::City { name: city_name.clone(), country_code: params.country_code.clone() })
+        } else {
+            Err(String::from("location should be specified: city_name, lat and lon, zip and country or city_id"))
+        }
+    }
+
+    // four decimals are about 10m, more precise coordinates only defeat caching
+    pub fn truncate_coordinate(coordinate: f64) -> String {
+        format!("{:.4}", (coordinate * 10_000.0).trunc() / 10_000.0)
+    }
+
+    fn coordinates_from_params(params: &LocationParams) -> Result<Location, String> {
+        let lat = params.lat.as_ref().and_then(|lat| lat.parse::<f64>().ok());
+        let lon = params.lon.as_ref().and_then(|lon| lon.parse::<f64>().ok());
+        match (lat, lon) {
+            (Some(lat), Some(lon)) if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) =>
+                Ok(Location::Coordinates { lat, lon }),
+            _ => Err(String::from("lat and lon should be numbers within -90..90 and -180..180"))
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Coordinates { lat, lon } => write!(f, "{},{}", lat, lon),
+            Location::PostalCode { zip, country } => write!(f, "{} {}", zip, country),
+            Location::City { name, country_code: Some(country_code) } => write!(f, "{},{}", name, country_code),
+            Location::City { name, country_code: None } => write!(f, "{}", name),
+            Location::ProviderCityId { provider, id } => write!(f, "{}:{}", provider, id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_city_location() {
+        let params = LocationParams {
+            city_name: Some("Paris".to_string()),
+            country_code: Some("FR".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Location::from_params(&params).unwrap(),
+            Location::City { name: "Paris".to_string(), country_code: Some("FR".to_string()) }
+        );
+    }
+
+    #[test]
+    fn it_prefers_coordinates_over_city_name() {
+        let params = LocationParams {
+            city_name: Some("Paris".to_string()),
+            lat: Some("33.66".to_string()),
+            lon: Some("-95.55".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(Location::from_params(&params).unwrap(), Location::Coordinates { lat: 33.66, lon: -95.55 });
+    }
+
+    #[test]
+    fn it_rejects_invalid_coordinates() {
+        let params = LocationParams { lat: Some("91".to_string()), lon: Some("10".to_string()), ..Default::default() };
+        assert!(Location::from_params(&params).is_err());
+
+        let params = LocationParams { lat: Some("55.7".to_string()), ..Default::default() };
+        assert!(Location::from_params(&params).is_err());
+    }
+
+    #[test]
+    fn it_builds_postal_code_location() {
+        let params = LocationParams { zip: Some("75001".to_string()), country: Some("FR".to_string()), ..Default::default() };
+        assert_eq!(
+            Location::from_params(&params).unwrap(),
+            Location::PostalCode { zip: "75001".to_string(), country: "FR".to_string() }
+        );
+
+        let params = LocationParams { zip: Some("75001".to_string()), ..Default::default() };
+        assert!(Location::from_params(&params).is_err());
+    }
+
+    #[test]
+    fn it_builds_provider_city_id_location() {
+        let params = LocationParams { city_id: Some("open_weather:551487".to_string()), ..Default::default() };
+        assert_eq!(
+            Location::from_params(&params).unwrap(),
+            Location::ProviderCityId { provider: "open_weather".to_string(), id: "551487".to_string() }
+        );
+
+        let params = LocationParams { city_id: Some("551487".to_string()), ..Default::default() };
+        assert!(Location::from_params(&params).is_err());
+    }
+
+    #[test]
+    fn it_truncates_coordinates_to_four_decimals() {
+        assert_eq!(Location::truncate_coordinate(59.91279), "59.9127");
+        assert_eq!(Location::truncate_coordinate(-97.08949876), "-97.0894");
+        assert_eq!(Location::truncate_coordinate(10.0), "10.0000");
+    }
+
+    #[test]
+    fn it_requires_some_location() {
+        assert!(Location::from_params(&LocationParams::default()).is_err());
+    }
+}
@@ -1,8 +1,19 @@
-use weather_reports::run;
+use weather_reports::{run, Config};
 use std::net::TcpListener;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:7878").expect("Failed to bind port 7878");
-    run(listener)?.await
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let listener = TcpListener::bind(&config.listen_address)
+        .unwrap_or_else(|error| panic!("Failed to bind {}: {}", config.listen_address, error));
+    run(listener, config)?.await
 }
@@ -0,0 +1,205 @@
+use crate::{ReportSpread, Spread, WeatherReport};
+
+// Providers are queried in metric units, so reports keep celsius, m/s, hPa and mm
+// internally and are converted only when rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnitSystem {
+    Metric,
+    Imperial,
+    Standard
+}
+
+pub struct UnitLabels {
+    pub temperature: &'static str,
+    pub wind_speed: &'static str,
+    pub pressure: &'static str,
+    pub precipitation: &'static str
+}
+
+const MPH_IN_METER_PER_SECOND : f64 = 2.236_936;
+const INHG_IN_HECTOPASCAL : f64 = 0.029_53;
+const MILLIMETERS_IN_INCH : f64 = 25.4;
+const KELVIN_AT_ZERO_CELSIUS : f64 = 273.15;
+
+impl UnitSystem {
+    pub fn from_param(units: &Option<String>) -> Result<Self, String> {
+        match units.as_deref().map(str::to_lowercase).as_deref() {
+            None | Some("metric") => Ok(UnitSystem::Metric),
+            Some("imperial") => Ok(UnitSystem::Imperial),
+            Some("standard") => Ok(UnitSystem::Standard),
+            Some(_) => Err(String::from("units should be one of metric, imperial or standard"))
+        }
+    }
+
+    pub fn labels(&self) -> UnitLabels {
+        match self {
+            UnitSystem::Metric => UnitLabels { temperature: "celsius", wind_speed: "m/s", pressure: "hPa", precipitation: "mm" },
+            UnitSystem::Imperial => UnitLabels { temperature: "fahrenheit", wind_speed: "mph", pressure: "inHg", precipitation: "in" },
+            UnitSystem::Standard => UnitLabels { temperature: "kelvin", wind_speed: "m/s", pressure: "hPa", precipitation: "mm" }
+        }
+    }
+
+    pub fn convert(&self, report: WeatherReport) -> WeatherReport {
+        if *self == UnitSystem::Metric {
+            return report;
+        }
+
+        let temperature = |celsius: f64| self.temperature(celsius);
+        WeatherReport {
+            temperature: temperature(report.temperature),
+            temperature_min: report.temperature_min.map(temperature),
+            temperature_max: report.temperature_max.map(temperature),
+            temperature_morning: report.temperature_morning.map(temperature),
+            temperature_evening: report.temperature_evening.map(temperature),
+            temperature_night: report.temperature_night.map(temperature),
+            wind_speed: report.wind_speed.map(|speed| self.wind_speed(speed)),
+            pressure: report.pressure.map(|pressure| self.pressure(pressure)),
+            precipitation: report.precipitation.map(|precipitation| self.precipitation(precipitation)),
+            ..report
+        }
+    }
+
+    pub fn convert_spread(&self, spread: ReportSpread) -> ReportSpread {
+        if *self == UnitSystem::Metric {
+            return spread;
+        }
+
+        // deviations are differences between values, so temperature offsets don't apply to them
+        let temperature = |spread: Spread| Spread {
+            min: self.temperature(spread.min),
+            max: self.temperature(spread.max),
+            std_dev: self.temperature_difference(spread.std_dev),
+            ..spread
+        };
+        let scaled = |convert: &dyn Fn(f64) -> f64, spread: Spread| Spread {
+            min: convert(spread.min),
+            max: convert(spread.max),
+            std_dev: convert(spread.std_dev),
+            ..spread
+        };
+        ReportSpread {
+            temperature: spread.temperature.map(temperature),
+            temperature_min: spread.temperature_min.map(temperature),
+            temperature_max: spread.temperature_max.map(temperature),
+            temperature_morning: spread.temperature_morning.map(temperature),
+            temperature_evening: spread.temperature_evening.map(temperature),
+            temperature_night: spread.temperature_night.map(temperature),
+            wind_speed: spread.wind_speed.map(|spread| scaled(&|speed| self.wind_speed(speed), spread)),
+            pressure: spread.pressure.map(|spread| scaled(&|pressure| self.pressure(pressure), spread)),
+            precipitation: spread.precipitation.map(|spread| scaled(&|precipitation| self.precipitation(precipitation), spread)),
+            ..spread
+        }
+    }
+
+    fn temperature(&self, celsius: f64) -> f64 {
+        match self {
+            UnitSystem::Metric => celsius,
+            UnitSystem::Imperial => round(celsius * 9.0 / 5.0 + 32.0),
+            UnitSystem::Standard => round(celsius + KELVIN_AT_ZERO_CELSIUS)
+        }
+    }
+
+    fn temperature_difference(&self, celsius: f64) -> f64 {
+        match self {
+            UnitSystem::Imperial => round(celsius * 9.0 / 5.0),
+            UnitSystem::Metric | UnitSystem::Standard => celsius
+        }
+    }
+
+    fn wind_speed(&self, meters_per_second: f64) -> f64 {
+        match self {
+            UnitSystem::Imperial => round(meters_per_second * MPH_IN_METER_PER_SECOND),
+            UnitSystem::Metric | UnitSystem::Standard => meters_per_second
+        }
+    }
+
+    fn pressure(&self, hectopascals: f64) -> f64 {
+        match self {
+            UnitSystem::Imperial => round(hectopascals * INHG_IN_HECTOPASCAL),
+            UnitSystem::Metric | UnitSystem::Standard => hectopascals
+        }
+    }
+
+    fn precipitation(&self, millimeters: f64) -> f64 {
+        match self {
+            UnitSystem::Imperial => round(millimeters / MILLIMETERS_IN_INCH),
+            UnitSystem::Metric | UnitSystem::Standard => millimeters
+        }
+    }
+}
+
+// converted values are rounded to hundredths, so floating point noise doesn't leak into output
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> WeatherReport {
+        WeatherReport {
+            temperature: -17.66,
+            temperature_max: Some(20.0),
+            humidity: Some(73.0),
+            wind_speed: Some(5.0),
+            wind_direction: Some(290.0),
+            pressure: Some(1021.5),
+            precipitation: Some(2.4),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_parses_units_param() {
+        assert_eq!(UnitSystem::from_param(&None), Ok(UnitSystem::Metric));
+        assert_eq!(UnitSystem::from_param(&Some("Imperial".to_string())), Ok(UnitSystem::Imperial));
+        assert_eq!(UnitSystem::from_param(&Some("standard".to_string())), Ok(UnitSystem::Standard));
+        assert!(UnitSystem::from_param(&Some("kelvin".to_string())).is_err());
+    }
+
+    #[test]
+    fn it_keeps_metric_values() {
+        let report = UnitSystem::Metric.convert(report());
+
+        assert_eq!(report.temperature, -17.66);
+        assert_eq!(report.pressure, Some(1021.5));
+    }
+
+    #[test]
+    fn it_converts_to_imperial() {
+        let report = UnitSystem::Imperial.convert(report());
+
+        assert_eq!(report.temperature, 0.21);
+        assert_eq!(report.temperature_max, Some(68.0));
+        assert_eq!(report.wind_speed, Some(11.18));
+        assert_eq!(report.pressure, Some(30.16));
+        assert_eq!(report.precipitation, Some(0.09));
+        assert_eq!(report.humidity, Some(73.0));
+        assert_eq!(report.wind_direction, Some(290.0));
+    }
+
+    #[test]
+    fn it_converts_temperature_to_kelvin() {
+        let report = UnitSystem::Standard.convert(report());
+
+        assert_eq!(report.temperature, 255.49);
+        assert_eq!(report.temperature_max, Some(293.15));
+        assert_eq!(report.wind_speed, Some(5.0));
+        assert_eq!(report.pressure, Some(1021.5));
+    }
+
+    #[test]
+    fn it_converts_temperature_spread_without_offset() {
+        let spread = ReportSpread {
+            temperature: Some(Spread { min: 10.0, max: 20.0, std_dev: 5.0, provider_count: 2 }),
+            wind_speed: Some(Spread { min: 1.0, max: 3.0, std_dev: 1.0, provider_count: 2 }),
+            ..Default::default()
+        };
+
+        let spread = UnitSystem::Imperial.convert_spread(spread);
+
+        assert_eq!(spread.temperature, Some(Spread { min: 50.0, max: 68.0, std_dev: 9.0, provider_count: 2 }));
+        assert_eq!(spread.wind_speed, Some(Spread { min: 2.24, max: 6.71, std_dev: 2.24, provider_count: 2 }));
+    }
+}
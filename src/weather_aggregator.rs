@@ -1,223 +1,842 @@
 mod weather_clients;
-
-use crate::WeatherReport;
-use std::error::Error;
+mod cache;
+mod circuit_breaker;
+mod aggregation;
+
+use crate::{AggregatedReport, Confidence, Location, ReportSpread, WeatherCondition, WeatherReport};
+use crate::config::{Config, ProviderConfig};
+use chrono::{Duration, NaiveDate};
+use futures::future::join_all;
+use crate::errors::{ProviderError, ProviderFailure, WeatherError};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use cache::{CachedProvider, CacheSettings, ResponseCache};
+use circuit_breaker::{BreakerSettings, CircuitBreakerProvider};
+use weather_clients::RetryPolicy;
+use weather_clients::custom::CustomProvider;
+use weather_clients::nws::NationalWeatherService;
+use weather_clients::met_norway::MetNorway;
+use weather_clients::open_meteo::OpenMeteo;
 use weather_clients::open_weather::OpenWeather;
 use weather_clients::weatherbit::Weatherbit;
-use average::Mean;
-use average::Estimate;
+use average::{Estimate, Mean};
+use aggregation::ValueStats;
+
+pub use weather_clients::{Capabilities, ProviderRegistry, WeatherProvider};
+pub use weather_clients::custom::CustomProviderDefinition;
+pub use circuit_breaker::CircuitStatus;
+pub use aggregation::{AGGREGATED_FIELDS, Aggregation, AggregationStrategy};
 
 #[derive(Clone)]
 pub struct AverageWeatherReport {
-    pub temperature: Mean,
-    pub unix_timestamp: Mean
+    pub temperature: ValueStats,
+    pub unix_timestamp: Mean,
+    pub utc_offset: i32,
+    pub timezone: Option<String>,
+    pub temperature_min: ValueStats,
+    pub temperature_max: ValueStats,
+    pub temperature_morning: ValueStats,
+    pub temperature_evening: ValueStats,
+    pub temperature_night: ValueStats,
+    pub humidity: ValueStats,
+    pub wind_speed: ValueStats,
+    pub wind_direction: CircularMean,
+    pub pressure: ValueStats,
+    pub cloud_cover: ValueStats,
+    pub precipitation: ValueStats,
+    pub conditions: Vec<WeatherCondition>
 }
 
 impl AverageWeatherReport {
     pub fn new() -> AverageWeatherReport {
-        AverageWeatherReport { temperature: Mean::new(), unix_timestamp: Mean::new() }
+        AverageWeatherReport {
+            temperature: ValueStats::new(),
+            unix_timestamp: Mean::new(),
+            utc_offset: 0,
+            timezone: None,
+            temperature_min: ValueStats::new(),
+            temperature_max: ValueStats::new(),
+            temperature_morning: ValueStats::new(),
+            temperature_evening: ValueStats::new(),
+            temperature_night: ValueStats::new(),
+            humidity: ValueStats::new(),
+            wind_speed: ValueStats::new(),
+            wind_direction: CircularMean::new(),
+            pressure: ValueStats::new(),
+            cloud_cover: ValueStats::new(),
+            precipitation: ValueStats::new(),
+            conditions: vec![]
+        }
     }
 
-    pub fn add(&mut self, weather_report: WeatherReport) -> &AverageWeatherReport {
-        self.temperature.add(weather_report.temperature);
+    pub fn add(&mut self, weather_report: WeatherReport, weight: f64) -> &AverageWeatherReport {
+        self.temperature.add(weather_report.temperature, weight);
         self.unix_timestamp.add(weather_report.unix_timestamp as f64);
+        // providers which could not resolve location timezone report zero offset
+        if self.utc_offset == 0 {
+            self.utc_offset = weather_report.utc_offset;
+        }
+        if self.timezone.is_none() {
+            self.timezone = weather_report.timezone;
+        }
+        add_optional(&mut self.temperature_min, weather_report.temperature_min, weight);
+        add_optional(&mut self.temperature_max, weather_report.temperature_max, weight);
+        add_optional(&mut self.temperature_morning, weather_report.temperature_morning, weight);
+        add_optional(&mut self.temperature_evening, weather_report.temperature_evening, weight);
+        add_optional(&mut self.temperature_night, weather_report.temperature_night, weight);
+        add_optional(&mut self.humidity, weather_report.humidity, weight);
+        add_optional(&mut self.wind_speed, weather_report.wind_speed, weight);
+        if let Some(wind_direction) = weather_report.wind_direction {
+            self.wind_direction.add(wind_direction);
+        }
+        add_optional(&mut self.pressure, weather_report.pressure, weight);
+        add_optional(&mut self.cloud_cover, weather_report.cloud_cover, weight);
+        add_optional(&mut self.precipitation, weather_report.precipitation, weight);
+        if let Some(condition) = weather_report.condition {
+            self.conditions.push(condition);
+        }
         self
     }
 
-    pub fn mean(&self) -> WeatherReport {
+    pub fn aggregate(&self, aggregation: &Aggregation) -> WeatherReport {
         WeatherReport {
-            temperature: self.temperature.mean(),
-            unix_timestamp: self.unix_timestamp.mean() as i64
+            temperature: aggregation.aggregate("temperature", &self.temperature).unwrap_or_default(),
+            unix_timestamp: self.unix_timestamp.mean() as i64,
+            utc_offset: self.utc_offset,
+            timezone: self.timezone.clone(),
+            temperature_min: aggregation.aggregate("temperature_min", &self.temperature_min),
+            temperature_max: aggregation.aggregate("temperature_max", &self.temperature_max),
+            temperature_morning: aggregation.aggregate("temperature_morning", &self.temperature_morning),
+            temperature_evening: aggregation.aggregate("temperature_evening", &self.temperature_evening),
+            temperature_night: aggregation.aggregate("temperature_night", &self.temperature_night),
+            humidity: aggregation.aggregate("humidity", &self.humidity),
+            wind_speed: aggregation.aggregate("wind_speed", &self.wind_speed),
+            wind_direction: self.wind_direction.mean(),
+            pressure: aggregation.aggregate("pressure", &self.pressure),
+            cloud_cover: aggregation.aggregate("cloud_cover", &self.cloud_cover),
+            precipitation: aggregation.aggregate("precipitation", &self.precipitation),
+            condition: self.most_common_condition()
         }
     }
+
+    pub fn spread(&self) -> ReportSpread {
+        ReportSpread {
+            temperature: self.temperature.spread(),
+            temperature_min: self.temperature_min.spread(),
+            temperature_max: self.temperature_max.spread(),
+            temperature_morning: self.temperature_morning.spread(),
+            temperature_evening: self.temperature_evening.spread(),
+            temperature_night: self.temperature_night.spread(),
+            humidity: self.humidity.spread(),
+            wind_speed: self.wind_speed.spread(),
+            pressure: self.pressure.spread(),
+            cloud_cover: self.cloud_cover.spread(),
+            precipitation: self.precipitation.spread()
+        }
+    }
+
+    fn most_common_condition(&self) -> Option<WeatherCondition> {
+        let count = |code: u32| self.conditions.iter().filter(|condition| condition.code == code).count();
+        self.conditions
+            .iter()
+            .rev()
+            .max_by_key(|condition| count(condition.code))
+            .cloned()
+    }
 }
 
-pub async fn get_current_weather(city_name: &str) -> Result<WeatherReport, String> {
-    let mut reports : Vec<WeatherReport> = vec![];
+// Angles can't be averaged directly (mean of 350 and 10 degrees is 0, not 180),
+// so directions are averaged as unit vectors.
+#[derive(Clone)]
+pub struct CircularMean {
+    sin: Mean,
+    cos: Mean
+}
 
-    let (open_weather_report, weatherbit_report) =
-        futures::join!(
-            get_open_weather_current(city_name),
-            get_weatherbit_current(city_name)
-        );
+impl CircularMean {
+    pub fn new() -> CircularMean {
+        CircularMean { sin: Mean::new(), cos: Mean::new() }
+    }
 
-    if open_weather_report.is_ok() {
-        reports.push(open_weather_report.unwrap());
-    };
-    if weatherbit_report.is_ok() {
-        reports.push(weatherbit_report.unwrap());
+    pub fn add(&mut self, degrees: f64) {
+        self.sin.add(degrees.to_radians().sin());
+        self.cos.add(degrees.to_radians().cos());
     }
 
-    if !reports.is_empty() {
-        Ok(average_report(reports))
+    pub fn mean(&self) -> Option<f64> {
+        let (sin, cos) = (self.sin.mean(), self.cos.mean());
+        if self.sin.is_empty() || sin.hypot(cos) < 1e-9 {
+            None
+        } else {
+            Some(sin.atan2(cos).to_degrees().rem_euclid(360.0))
+        }
+    }
+}
+
+fn add_optional(stats: &mut ValueStats, value: Option<f64>, weight: f64) {
+    if let Some(value) = value {
+        stats.add(value, weight);
+    }
+}
+
+// temperature deviations in celsius, below which providers are considered to agree
+const HIGH_CONFIDENCE_STD_DEV : f64 = 1.0;
+const MEDIUM_CONFIDENCE_STD_DEV : f64 = 2.5;
+
+// a single provider gives nothing to cross-check, so its values are low confidence
+fn confidence(spread: &ReportSpread) -> Confidence {
+    let temperatures = [&spread.temperature, &spread.temperature_min, &spread.temperature_max];
+    let provider_count = spread.temperature.as_ref().map_or(0, |spread| spread.provider_count);
+    let std_dev = temperatures
+        .iter()
+        .filter_map(|spread| spread.as_ref())
+        .map(|spread| spread.std_dev)
+        .fold(0.0, f64::max);
+
+    if provider_count < 2 || std_dev > MEDIUM_CONFIDENCE_STD_DEV {
+        Confidence::Low
+    } else if std_dev > HIGH_CONFIDENCE_STD_DEV {
+        Confidence::Medium
     } else {
-        Err(String::from("Could not find weather data"))
+        Confidence::High
     }
 }
 
-pub async fn get_forecast_weather(city_name: &str, days_count: usize) -> Result<Vec<WeatherReport>, String> {
-    let mut reports : Vec<Vec<WeatherReport>> = vec![];
+fn aggregated_report(date: NaiveDate, report: WeatherReport, average: &AverageWeatherReport, providers: Vec<&'static str>) -> AggregatedReport {
+    let spread = average.spread();
+    AggregatedReport { date, report, providers, confidence: confidence(&spread), spread }
+}
 
-    let (open_weather_report, weatherbit_report) =
-        futures::join!(
-            get_open_weather_forecast(city_name, days_count),
-            get_weatherbit_forecast(city_name, days_count)
-        );
+pub fn registry_from_config(config: &Config) -> ProviderRegistry {
+    let cache = Arc::new(ResponseCache::new(CacheSettings {
+        current_ttl: std::time::Duration::from_secs(config.cache.current_ttl_secs),
+        forecast_ttl: std::time::Duration::from_secs(config.cache.forecast_ttl_secs),
+        ..CacheSettings::default()
+    }));
 
-    if open_weather_report.is_ok() {
-        reports.push(open_weather_report.unwrap());
+    let breaker_settings = BreakerSettings {
+        failure_threshold: config.circuit_breaker.failure_threshold,
+        open_duration: std::time::Duration::from_secs(config.circuit_breaker.open_secs)
+    };
+    let retry = RetryPolicy {
+        max_retries: config.retry.max_retries,
+        base_delay: std::time::Duration::from_millis(config.retry.base_delay_ms),
+        max_delay: std::time::Duration::from_millis(config.retry.max_delay_ms),
+        deadline: None
     };
-    if weatherbit_report.is_ok() {
-        reports.push(weatherbit_report.unwrap());
-    }
 
-    if !reports.is_empty() {
-        Ok(average_forecast_report(reports))
-    } else {
-        Err(String::from("Could not find weather data"))
+    let client = http_client(config);
+    let mut registry = ProviderRegistry::new();
+    for (name, provider_config) in config.active_providers() {
+        let timeout = config.provider_timeout(provider_config);
+        // timeout limits each attempt, and retries of a slow provider should not take much longer than that
+        let retry = RetryPolicy { deadline: Some(timeout + retry.max_delay), ..retry };
+        if let Some(provider) = build_provider(client.clone(), name, provider_config, &config.user_agent, timeout, retry) {
+            let provider = Box::new(CircuitBreakerProvider::new(provider, breaker_settings));
+            registry.register(Box::new(CachedProvider::new(provider, cache.clone())));
+        }
     }
+    registry
 }
 
-pub async fn get_specific_day_weather(city_name: &str, days_since: usize) -> Result<WeatherReport, String> {
-    let mut report = get_forecast_weather(city_name, days_since + 1).await?;
-    Ok(report.remove(days_since))
+// one pooled client is shared by every provider, request timeouts are set per provider
+fn http_client(config: &Config) -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent(config.user_agent.as_str())
+        .connect_timeout(std::time::Duration::from_secs(config.connect_timeout_secs))
+        .pool_idle_timeout(std::time::Duration::from_secs(90))
+        .pool_max_idle_per_host(8)
+        .tcp_keepalive(std::time::Duration::from_secs(60))
+        .build()
+        .expect("Failed to build http client")
 }
 
-async fn get_open_weather_current(city_name: &str) -> Result<WeatherReport, Box<dyn Error>> {
-    open_weather_client().get_current(city_name).await
+fn build_provider(client: reqwest::Client, name: &str, config: &ProviderConfig, user_agent: &str, timeout: std::time::Duration, retry: RetryPolicy) -> Option<Box<dyn WeatherProvider>> {
+    let api_key = config.api_key.clone().unwrap_or_default();
+    let base_url = config.base_url.clone();
+    if let (Some(definition), Some(base_url)) = (&config.custom, &base_url) {
+        let provider = CustomProvider::new(name, client, api_key, base_url.clone(), definition.clone());
+        return Some(Box::new(provider.with_timeout(timeout).with_retry(retry)));
+    }
+    match name {
+        "open_weather" => {
+            let provider = match base_url {
+                Some(base_url) => OpenWeather::new_with_prefix(client, api_key, base_url),
+                None => OpenWeather::new(client, api_key)
+            };
+            Some(Box::new(provider.with_timeout(timeout).with_retry(retry)))
+        },
+        "weatherbit" => {
+            let provider = match base_url {
+                Some(base_url) => Weatherbit::new_with_prefix(client, api_key, base_url),
+                None => Weatherbit::new(client, api_key)
+            };
+            Some(Box::new(provider.with_timeout(timeout).with_retry(retry)))
+        },
+        "open_meteo" => {
+            let provider = match base_url {
+                Some(base_url) => OpenMeteo::new_with_prefix(client, base_url),
+                None => OpenMeteo::new(client)
+            };
+            Some(Box::new(provider.with_timeout(timeout).with_retry(retry)))
+        },
+        "nws" => {
+            let provider = match base_url {
+                Some(base_url) => NationalWeatherService::new_with_prefix(client, user_agent.to_string(), base_url),
+                None => NationalWeatherService::new(client, user_agent.to_string())
+            };
+            Some(Box::new(provider.with_timeout(timeout).with_retry(retry)))
+        },
+        "met_norway" => {
+            let provider = match base_url {
+                Some(base_url) => MetNorway::new_with_prefix(client, user_agent.to_string(), base_url),
+                None => MetNorway::new(client, user_agent.to_string())
+            };
+            Some(Box::new(provider.with_timeout(timeout).with_retry(retry)))
+        },
+        _ => None
+    }
 }
 
-async fn get_weatherbit_current(city_name: &str) -> Result<WeatherReport, Box<dyn Error>> {
-    weatherbit_client().get_current(city_name).await
+pub async fn get_current_weather(registry: &ProviderRegistry, location: &Location, aggregation: &Aggregation) -> Result<AggregatedReport, WeatherError> {
+    let requests = registry
+        .providers()
+        .filter(|provider| provider.capabilities().current)
+        .map(|provider| async move { (provider.name(), provider.get_current(location).await) });
+
+    let reports = collect_successful_reports(join_all(requests).await)?;
+    let providers = reports.iter().map(|(provider_name, _)| *provider_name).collect();
+    let average = average_report(reports, aggregation);
+    let report = average.aggregate(aggregation);
+    Ok(aggregated_report(report.local_date(), report, &average, providers))
 }
 
-async fn get_open_weather_forecast(city_name: &str, days_count: usize) -> Result<Vec<WeatherReport>, Box<dyn Error>> {
-    open_weather_client().get_forecast(city_name, days_count).await
+pub async fn get_forecast_weather(registry: &ProviderRegistry, location: &Location, days_count: usize, aggregation: &Aggregation) -> Result<Vec<AggregatedReport>, WeatherError> {
+    let requests = registry
+        .providers()
+        .filter(|provider| provider.capabilities().forecast)
+        .map(|provider| async move {
+            let provider_days_count = days_count.min(provider.capabilities().max_forecast_days);
+            (provider.name(), provider.get_forecast(location, provider_days_count).await)
+        });
+
+    let reports = collect_successful_reports(join_all(requests).await)?;
+    let mut forecast = average_forecast_report(reports, aggregation);
+    forecast.truncate(days_count);
+    Ok(forecast)
 }
 
-async fn get_weatherbit_forecast(city_name: &str, days_count: usize) -> Result<Vec<WeatherReport>, Box<dyn Error>> {
-    weatherbit_client().get_forecast(city_name, days_count).await
+pub async fn get_hourly_weather(registry: &ProviderRegistry, location: &Location, hours_count: usize, aggregation: &Aggregation) -> Result<Vec<AggregatedReport>, WeatherError> {
+    let requests = registry
+        .providers()
+        .filter(|provider| provider.capabilities().hourly)
+        .map(|provider| async move {
+            let provider_hours_count = hours_count.min(provider.capabilities().max_forecast_hours);
+            (provider.name(), provider.get_hourly(location, provider_hours_count).await)
+        });
+
+    let reports = collect_successful_reports(join_all(requests).await)?;
+    let mut hourly = average_hourly_report(reports, aggregation);
+    hourly.truncate(hours_count);
+    Ok(hourly)
 }
 
-fn open_weather_client() -> OpenWeather {
-    OpenWeather::new(open_weather_api_key())
+pub async fn get_specific_day_weather(registry: &ProviderRegistry, location: &Location, days_since: usize, aggregation: &Aggregation) -> Result<AggregatedReport, WeatherError> {
+    let forecast = get_forecast_weather(registry, location, days_since + 1, aggregation).await?;
+    let requested_date = forecast
+        .first()
+        .map(|first_day| first_day.date + Duration::days(days_since as i64));
+
+    forecast
+        .into_iter()
+        .find(|day| Some(day.date) == requested_date)
+        .ok_or_else(|| WeatherError::DateNotCovered(format!("Providers don't have weather data {} days ahead", days_since)))
 }
 
-fn weatherbit_client() -> Weatherbit {
-    Weatherbit::new(weatherbit_api_key())
+// `today` is the UTC date, location's local date may differ from it by one day
+pub async fn get_weather_on_date(registry: &ProviderRegistry, location: &Location, date: NaiveDate, today: NaiveDate, horizon_days: usize, aggregation: &Aggregation) -> Result<AggregatedReport, WeatherError> {
+    let days_ahead = (date - today).num_days();
+    if days_ahead < -1 {
+        return Err(WeatherError::InvalidRequest(format!("{} is in the past, use /history for past dates", date)));
+    }
+    if days_ahead >= horizon_days as i64 {
+        return Err(WeatherError::DateNotCovered(format!("{} is beyond providers' forecast horizon of {} days", date, horizon_days)));
+    }
+
+    let days_count = ((days_ahead + 2) as usize).min(horizon_days);
+    let forecast = get_forecast_weather(registry, location, days_count, aggregation).await?;
+    let covered = match (forecast.first(), forecast.last()) {
+        (Some(first), Some(last)) => format!("{}..{}", first.date, last.date),
+        _ => String::from("no dates")
+    };
+    if forecast.first().is_some_and(|first| date < first.date) {
+        return Err(WeatherError::InvalidRequest(format!("{} is in the past at this location, use /history for past dates", date)));
+    }
+
+    forecast
+        .into_iter()
+        .find(|day| day.date == date)
+        .ok_or_else(|| WeatherError::DateNotCovered(format!("Providers don't have weather data for {}, they cover {}", date, covered)))
 }
 
-fn weatherbit_api_key() -> String {
-    std::env::var("WEATHERBIT_API_KEY").unwrap()
+pub async fn get_history_weather(registry: &ProviderRegistry, location: &Location, date: NaiveDate, today: NaiveDate, aggregation: &Aggregation) -> Result<AggregatedReport, WeatherError> {
+    let days_ago = (today - date).num_days();
+    if days_ago < 1 {
+        return Err(WeatherError::InvalidRequest(String::from("date should be in the past, use /daily for today and future days")));
+    }
+
+    let (covering, not_covering): (Vec<_>, Vec<_>) = registry
+        .providers()
+        .filter(|provider| provider.capabilities().history_days > 0)
+        .partition(|provider| days_ago as usize <= provider.capabilities().history_days);
+    if covering.is_empty() {
+        let windows: Vec<String> = not_covering
+            .iter()
+            .map(|provider| format!("{} keeps last {} days", provider.name(), provider.capabilities().history_days))
+            .collect();
+        return Err(WeatherError::DateNotCovered(format!("{} is outside providers' history window ({})", date, windows.join(", "))));
+    }
+
+    let requests = covering
+        .into_iter()
+        .map(|provider| async move { (provider.name(), provider.get_history(location, date).await) });
+
+    let reports = collect_successful_reports(join_all(requests).await)?;
+    let providers = reports.iter().map(|(provider_name, _)| *provider_name).collect();
+    let average = average_report(reports, aggregation);
+    Ok(aggregated_report(date, day_report(average.aggregate(aggregation)), &average, providers))
 }
 
-fn open_weather_api_key() -> String {
-    std::env::var("OPEN_WEATHER_APPID").unwrap()
+type ProviderResponse<T> = (&'static str, Result<T, ProviderError>);
+
+fn collect_successful_reports<T>(responses: Vec<ProviderResponse<T>>) -> Result<Vec<(&'static str, T)>, WeatherError> {
+    let mut reports = vec![];
+    let mut failures = vec![];
+    for (provider_name, response) in responses {
+        match response {
+            Ok(report) => reports.push((provider_name, report)),
+            Err(error) => failures.push(ProviderFailure::new(provider_name, &error))
+        }
+    }
+
+    if !reports.is_empty() {
+        Ok(reports)
+    } else {
+        Err(WeatherError::from_failures(failures))
+    }
 }
 
-fn average_report(reports: Vec<WeatherReport>) -> WeatherReport {
+fn average_report(reports: Vec<(&'static str, WeatherReport)>, aggregation: &Aggregation) -> AverageWeatherReport {
     reports
         .into_iter()
         .fold(
             AverageWeatherReport::new(),
-            |mut average, report| { average.add(report); average }
+            |mut average, (provider_name, report)| { average.add(report, aggregation.weight(provider_name)); average }
         )
-        .mean()
 }
 
-fn average_forecast_report(reports: Vec<Vec<WeatherReport>>) -> Vec<WeatherReport> {
-    let average_reports = vec![AverageWeatherReport::new(); reports[0].len()];
-    reports
-        .into_iter()
-        .fold(average_reports, |average_forecast_report, forecast_report| {
-            average_forecast_report.into_iter()
-                .zip(forecast_report.into_iter())
-                .map(|(mut average, report)| { average.add(report); average })
-                .collect()
+fn average_forecast_report(reports: Vec<(&'static str, Vec<WeatherReport>)>, aggregation: &Aggregation) -> Vec<AggregatedReport> {
+    let mut days : BTreeMap<NaiveDate, (AverageWeatherReport, Vec<&'static str>)> = BTreeMap::new();
+    for (provider_name, forecast_report) in reports {
+        for report in forecast_report {
+            let (average, providers) = days
+                .entry(report.local_date())
+                .or_insert_with(|| (AverageWeatherReport::new(), vec![]));
+            average.add(report, aggregation.weight(provider_name));
+            if !providers.contains(&provider_name) {
+                providers.push(provider_name);
+            }
+        }
+    }
+
+    days.into_iter()
+        .map(|(date, (average, providers))| aggregated_report(date, day_report(average.aggregate(aggregation)), &average, providers))
+        .collect()
+}
+
+// averaging timestamps of different providers gives an arbitrary instant,
+// so a day is identified by its local midnight
+fn day_report(report: WeatherReport) -> WeatherReport {
+    WeatherReport { unix_timestamp: report.local_day_start(), ..report }
+}
+
+const HOUR: i64 = 3600;
+
+// providers may report an hour by its start or by some moment inside it, so reports are bucketed by hour start
+fn average_hourly_report(reports: Vec<(&'static str, Vec<WeatherReport>)>, aggregation: &Aggregation) -> Vec<AggregatedReport> {
+    let mut hours : BTreeMap<i64, (AverageWeatherReport, Vec<&'static str>)> = BTreeMap::new();
+    for (provider_name, hourly_report) in reports {
+        for report in hourly_report {
+            let (average, providers) = hours
+                .entry(report.unix_timestamp.div_euclid(HOUR) * HOUR)
+                .or_insert_with(|| (AverageWeatherReport::new(), vec![]));
+            average.add(report, aggregation.weight(provider_name));
+            if !providers.contains(&provider_name) {
+                providers.push(provider_name);
+            }
+        }
+    }
+
+    hours.into_iter()
+        .map(|(hour, (average, providers))| {
+            let report = WeatherReport { unix_timestamp: hour, ..average.aggregate(aggregation) };
+            aggregated_report(report.local_date(), report, &average, providers)
         })
-        .into_iter()
-        .map(|average_report| average_report.mean())
         .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Spread;
+    use async_trait::async_trait;
+    use weather_clients::Capabilities;
+
+    const DAY : i64 = 86400;
+
+    fn report(temperature: f64, unix_timestamp: i64) -> WeatherReport {
+        WeatherReport { temperature, unix_timestamp, ..Default::default() }
+    }
+
+    struct StubProvider {
+        name: &'static str,
+        temperature: Option<f64>
+    }
+
+    #[async_trait(?Send)]
+    impl WeatherProvider for StubProvider {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities { current: true, forecast: true, max_forecast_days: 3, hourly: false, max_forecast_hours: 0, history_days: 3 }
+        }
+
+        async fn get_current(&self, _location: &Location) -> Result<WeatherReport, ProviderError> {
+            match self.temperature {
+                Some(temperature) => Ok(report(temperature, 10)),
+                None => Err(ProviderError::Unavailable("provider is down".to_string()))
+            }
+        }
+
+        async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+            let current = self.get_current(location).await?;
+            Ok((0..days_count.min(3) as i64).map(|day| report(current.temperature, day * DAY)).collect())
+        }
+
+        async fn get_history(&self, location: &Location, date: NaiveDate) -> Result<WeatherReport, ProviderError> {
+            let current = self.get_current(location).await?;
+            Ok(report(current.temperature, date.and_hms(12, 0, 0).timestamp()))
+        }
+    }
+
+    fn aggregate(reports: Vec<WeatherReport>) -> WeatherReport {
+        let reports = reports.into_iter().map(|report| ("stub", report)).collect();
+        average_report(reports, &Aggregation::default()).aggregate(&Aggregation::default())
+    }
+
+    fn kazan() -> Location {
+        Location::City { name: "kazan".to_string(), country_code: None }
+    }
+
+    fn stub_registry(temperatures: Vec<Option<f64>>) -> ProviderRegistry {
+        let mut registry = ProviderRegistry::new();
+        for temperature in temperatures {
+            registry.register(Box::new(StubProvider { name: "stub", temperature }));
+        }
+        registry
+    }
+
+    #[actix_rt::test]
+    async fn fans_out_current_weather_to_all_registered_providers() {
+        let registry = stub_registry(vec![Some(2.0), Some(4.0), Some(9.0)]);
+
+        let current = get_current_weather(&registry, &kazan(), &Aggregation::default()).await.unwrap();
+        assert_eq!(current.report.temperature, 5.0);
+        assert_eq!(current.providers, vec!["stub", "stub", "stub"]);
+    }
+
+    #[actix_rt::test]
+    async fn skips_failed_providers() {
+        let registry = stub_registry(vec![Some(2.0), None]);
+
+        let forecast = get_forecast_weather(&registry, &kazan(), 2, &Aggregation::default()).await.unwrap();
+        assert_eq!(forecast.len(), 2);
+        assert_eq!(forecast[1].report.temperature, 2.0);
+    }
+
+    #[actix_rt::test]
+    async fn fails_when_no_provider_responds() {
+        let registry = stub_registry(vec![None, None]);
+
+        match get_current_weather(&registry, &kazan(), &Aggregation::default()).await {
+            Err(WeatherError::AllProvidersDown(failures)) => assert_eq!(failures.len(), 2),
+            _ => panic!("expected all providers to be down")
+        }
+        assert!(get_current_weather(&ProviderRegistry::new(), &kazan(), &Aggregation::default()).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn returns_error_instead_of_missing_day() {
+        let registry = stub_registry(vec![Some(2.0)]);
+
+        assert_eq!(get_specific_day_weather(&registry, &kazan(), 2, &Aggregation::default()).await.unwrap().report.temperature, 2.0);
+        assert!(get_specific_day_weather(&registry, &kazan(), 3, &Aggregation::default()).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn asks_providers_for_no_more_days_than_they_support() {
+        let registry = stub_registry(vec![Some(2.0)]);
+
+        let forecast = get_forecast_weather(&registry, &kazan(), 10, &Aggregation::default()).await.unwrap();
+        assert_eq!(forecast.len(), 3);
+        assert_eq!(registry.max_forecast_days(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn finds_forecast_for_explicit_date() {
+        let registry = stub_registry(vec![Some(2.0)]);
+        let today = NaiveDate::from_ymd(1970, 1, 1);
+
+        let day = get_weather_on_date(&registry, &kazan(), NaiveDate::from_ymd(1970, 1, 3), today, 3, &Aggregation::default()).await.unwrap();
+        assert_eq!(day.date, NaiveDate::from_ymd(1970, 1, 3));
+
+        let beyond_horizon = get_weather_on_date(&registry, &kazan(), NaiveDate::from_ymd(1970, 1, 5), today, 3, &Aggregation::default()).await;
+        assert!(matches!(beyond_horizon, Err(WeatherError::DateNotCovered(_))));
+        let past = get_weather_on_date(&registry, &kazan(), NaiveDate::from_ymd(1969, 12, 20), today, 3, &Aggregation::default()).await;
+        assert!(matches!(past, Err(WeatherError::InvalidRequest(_))));
+        let not_reported = get_weather_on_date(&registry, &kazan(), NaiveDate::from_ymd(1970, 1, 4), today, 7, &Aggregation::default()).await;
+        assert!(matches!(not_reported, Err(WeatherError::DateNotCovered(_))));
+    }
+
+    #[actix_rt::test]
+    async fn averages_history_within_providers_window() {
+        let registry = stub_registry(vec![Some(2.0), Some(4.0)]);
+        let today = NaiveDate::from_ymd(2021, 2, 23);
+
+        let history = get_history_weather(&registry, &kazan(), NaiveDate::from_ymd(2021, 2, 20), today, &Aggregation::default()).await.unwrap();
+        assert_eq!(history.date, NaiveDate::from_ymd(2021, 2, 20));
+        assert_eq!(history.report.temperature, 3.0);
+
+        match get_history_weather(&registry, &kazan(), NaiveDate::from_ymd(2021, 2, 19), today, &Aggregation::default()).await {
+            Err(WeatherError::DateNotCovered(message)) => assert!(message.contains("stub keeps last 3 days")),
+            _ => panic!("expected date not covered error")
+        }
+        assert!(matches!(get_history_weather(&registry, &kazan(), today, today, &Aggregation::default()).await, Err(WeatherError::InvalidRequest(_))));
+    }
 
     #[test]
     fn averages_several_weather_reports() {
         let reports = vec![
-            WeatherReport { temperature: 2.0, unix_timestamp: 10 },
-            WeatherReport { temperature: 4.0, unix_timestamp: 20 },
-            WeatherReport { temperature: 2.0, unix_timestamp: 10 },
-            WeatherReport { temperature: 8.0, unix_timestamp: 20 }
+            report(2.0, 10),
+            report(4.0, 20),
+            report(2.0, 10),
+            report(8.0, 20)
         ];
 
-        let average_report = average_report(reports);
+        let average_report = aggregate(reports);
         assert_eq!(average_report.temperature, 4.0);
         assert_eq!(average_report.unix_timestamp, 15);
     }
 
+    #[actix_rt::test]
+    async fn reports_spread_of_provider_values() {
+        let registry = stub_registry(vec![Some(0.0), Some(20.0)]);
+
+        let current = get_current_weather(&registry, &kazan(), &Aggregation::default()).await.unwrap();
+        assert_eq!(current.report.temperature, 10.0);
+        assert_eq!(current.spread.temperature, Some(Spread { min: 0.0, max: 20.0, std_dev: 10.0, provider_count: 2 }));
+        assert_eq!(current.spread.humidity, None);
+        assert_eq!(current.confidence, Confidence::Low);
+    }
+
+    #[actix_rt::test]
+    async fn derives_confidence_from_provider_agreement() {
+        let agreeing = get_current_weather(&stub_registry(vec![Some(9.5), Some(10.5)]), &kazan(), &Aggregation::default()).await.unwrap();
+        assert_eq!(agreeing.confidence, Confidence::High);
+
+        let diverging = get_current_weather(&stub_registry(vec![Some(8.0), Some(12.0)]), &kazan(), &Aggregation::default()).await.unwrap();
+        assert_eq!(diverging.confidence, Confidence::Medium);
+
+        let single = get_current_weather(&stub_registry(vec![Some(10.0), None]), &kazan(), &Aggregation::default()).await.unwrap();
+        assert_eq!(single.spread.temperature.unwrap().std_dev, 0.0);
+        assert_eq!(single.confidence, Confidence::Low);
+    }
+
     #[test]
     fn averages_single_weather_report() {
         let reports = vec![
-            WeatherReport { temperature: 2.0, unix_timestamp: 10 },
+            report(2.0, 10),
         ];
 
-        let average_report = average_report(reports);
+        let average_report = aggregate(reports);
         assert_eq!(average_report.temperature, 2.0);
         assert_eq!(average_report.unix_timestamp, 10);
     }
 
+    #[test]
+    fn averages_optional_weather_details() {
+        let reports = vec![
+            WeatherReport { humidity: Some(70.0), pressure: Some(1000.0), ..report(2.0, 10) },
+            WeatherReport { humidity: Some(80.0), precipitation: Some(1.5), ..report(4.0, 20) },
+            report(6.0, 30)
+        ];
+
+        let average_report = aggregate(reports);
+        assert_eq!(average_report.humidity, Some(75.0));
+        assert_eq!(average_report.pressure, Some(1000.0));
+        assert_eq!(average_report.precipitation, Some(1.5));
+        assert_eq!(average_report.wind_speed, None);
+    }
+
+    #[actix_rt::test]
+    async fn averages_with_requested_strategy() {
+        let registry = stub_registry(vec![Some(1.0), Some(2.0), Some(30.0)]);
+
+        let median = get_current_weather(&registry, &kazan(), &Aggregation::default().with_strategy(AggregationStrategy::Median)).await.unwrap();
+        assert_eq!(median.report.temperature, 2.0);
+        assert_eq!(median.spread.temperature.unwrap().max, 30.0);
+    }
+
+    #[test]
+    fn averages_with_provider_weights() {
+        let mut config = Config::default();
+        config.providers.get_mut("open_weather").unwrap().weight = 3.0;
+        config.aggregation.fields.insert("temperature".to_string(), AggregationStrategy::WeightedMean);
+        let reports = vec![
+            ("open_weather", vec![WeatherReport { humidity: Some(60.0), ..report(10.0, 0) }]),
+            ("weatherbit", vec![WeatherReport { humidity: Some(90.0), ..report(20.0, 0) }])
+        ];
+
+        let day = &average_forecast_report(reports, &Aggregation::from_config(&config))[0];
+        assert_eq!(day.report.temperature, 12.5);
+        assert_eq!(day.report.humidity, Some(75.0));
+    }
+
+    #[test]
+    fn averages_daily_temperature_ranges() {
+        let reports = vec![
+            ("first", vec![WeatherReport { temperature_min: Some(-3.0), temperature_max: Some(5.0), temperature_night: Some(-2.0), ..report(2.0, 10) }]),
+            ("second", vec![WeatherReport { temperature_min: Some(-1.0), temperature_max: Some(7.0), ..report(4.0, 20) }])
+        ];
+
+        let day = &average_forecast_report(reports, &Aggregation::default())[0];
+        assert_eq!(day.report.temperature_min, Some(-2.0));
+        assert_eq!(day.report.temperature_max, Some(6.0));
+        assert_eq!(day.report.temperature_night, Some(-2.0));
+        assert_eq!(day.report.temperature_morning, None);
+    }
+
+    #[test]
+    fn averages_wind_direction_on_circle() {
+        let reports = vec![
+            WeatherReport { wind_direction: Some(350.0), ..report(2.0, 10) },
+            WeatherReport { wind_direction: Some(30.0), ..report(2.0, 10) }
+        ];
+
+        let wind_direction = aggregate(reports).wind_direction.unwrap();
+        assert!((wind_direction - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn picks_most_common_weather_condition() {
+        let condition = |code: u32| Some(WeatherCondition { code, description: code.to_string() });
+        let reports = vec![
+            WeatherReport { condition: condition(600), ..report(2.0, 10) },
+            WeatherReport { condition: condition(800), ..report(2.0, 10) },
+            WeatherReport { condition: condition(800), ..report(2.0, 10) }
+        ];
+
+        assert_eq!(aggregate(reports).condition.unwrap().code, 800);
+    }
+
     #[test]
     fn averages_several_weather_forecast_reports() {
         let reports = vec![
-            vec![
-                WeatherReport { temperature: 4.0, unix_timestamp: 10 },
-                WeatherReport { temperature: 4.0, unix_timestamp: 10 },
-                WeatherReport { temperature: 2.0, unix_timestamp: 10 }
-            ],
-            vec![
-                WeatherReport { temperature: 4.0, unix_timestamp: 10 },
-                WeatherReport { temperature: 4.0, unix_timestamp: 20 },
-                WeatherReport { temperature: 4.0, unix_timestamp: 30 }
-            ],
-            vec![
-                WeatherReport { temperature: 6.0, unix_timestamp: 10 },
-                WeatherReport { temperature: 4.0, unix_timestamp: 20 },
-                WeatherReport { temperature: 6.0, unix_timestamp: 20 }
-            ],
-            vec![
-                WeatherReport { temperature: 6.0, unix_timestamp: 10 },
-                WeatherReport { temperature: 4.0, unix_timestamp: 10 },
-                WeatherReport { temperature: 2.0, unix_timestamp: 20 }
-            ]
+            ("first", vec![report(4.0, 10), report(4.0, DAY + 10), report(2.0, 2 * DAY + 10)]),
+            ("second", vec![report(4.0, 10), report(4.0, DAY + 20), report(4.0, 2 * DAY + 30)]),
+            ("third", vec![report(6.0, 10), report(4.0, DAY + 20), report(6.0, 2 * DAY + 20)]),
+            ("fourth", vec![report(6.0, 10), report(4.0, DAY + 10), report(2.0, 2 * DAY + 20)])
         ];
 
-        let average_report = average_forecast_report(reports);
+        let average_report = average_forecast_report(reports, &Aggregation::default());
 
-        assert_eq!(average_report[0].temperature, 5.0);
-        assert_eq!(average_report[0].unix_timestamp, 10);
-        assert_eq!(average_report[1].temperature, 4.0);
-        assert_eq!(average_report[1].unix_timestamp, 15);
-        assert_eq!(average_report[2].temperature, 3.5);
-        assert_eq!(average_report[2].unix_timestamp, 20);
+        assert_eq!(average_report[0].report.temperature, 5.0);
+        assert_eq!(average_report[0].report.unix_timestamp, 0);
+        assert_eq!(average_report[1].report.temperature, 4.0);
+        assert_eq!(average_report[1].report.unix_timestamp, DAY);
+        assert_eq!(average_report[2].report.temperature, 3.5);
+        assert_eq!(average_report[2].report.unix_timestamp, 2 * DAY);
+        assert_eq!(average_report[2].providers, vec!["first", "second", "third", "fourth"]);
     }
 
     #[test]
     fn averages_single_weather_forecast_report() {
         let reports = vec![
-            vec![
-                WeatherReport { temperature: 2.0, unix_timestamp: 10 },
-                WeatherReport { temperature: 3.0, unix_timestamp: 33 }
-            ]
+            ("first", vec![report(2.0, 10), report(3.0, DAY + 33)])
+        ];
+
+        let average_report = average_forecast_report(reports, &Aggregation::default());
+
+        assert_eq!(average_report[0].report.temperature, 2.0);
+        assert_eq!(average_report[0].report.unix_timestamp, 0);
+        assert_eq!(average_report[1].report.temperature, 3.0);
+        assert_eq!(average_report[1].report.unix_timestamp, DAY);
+    }
+
+    #[test]
+    fn aligns_forecast_reports_by_date() {
+        let reports = vec![
+            ("first", vec![report(1.0, 10), report(2.0, DAY + 10), report(3.0, 2 * DAY + 10)]),
+            ("second", vec![report(4.0, DAY + 10), report(6.0, 2 * DAY + 10), report(8.0, 3 * DAY + 10)])
+        ];
+
+        let average_report = average_forecast_report(reports, &Aggregation::default());
+
+        assert_eq!(average_report.len(), 4);
+        assert_eq!(average_report[0].date, NaiveDate::from_ymd(1970, 1, 1));
+        assert_eq!(average_report[0].report.temperature, 1.0);
+        assert_eq!(average_report[0].providers, vec!["first"]);
+        assert_eq!(average_report[1].report.temperature, 3.0);
+        assert_eq!(average_report[2].report.temperature, 4.5);
+        assert_eq!(average_report[2].providers, vec!["first", "second"]);
+        assert!(!average_report[2].is_single_source());
+        assert_eq!(average_report[3].report.temperature, 8.0);
+        assert_eq!(average_report[3].providers, vec!["second"]);
+        assert!(average_report[3].is_single_source());
+    }
+
+    #[test]
+    fn buckets_forecast_reports_by_local_date() {
+        let tokyo_morning = WeatherReport { utc_offset: 9 * 3600, ..report(5.0, DAY - 3600) };
+        let reports = vec![
+            ("first", vec![tokyo_morning]),
+            ("second", vec![report(7.0, DAY + 10)])
+        ];
+
+        let average_report = average_forecast_report(reports, &Aggregation::default());
+
+        assert_eq!(average_report.len(), 1);
+        assert_eq!(average_report[0].date, NaiveDate::from_ymd(1970, 1, 2));
+        assert_eq!(average_report[0].report.temperature, 6.0);
+        assert_eq!(average_report[0].report.unix_timestamp, DAY - 9 * 3600);
+    }
+
+    #[test]
+    fn aligns_hourly_reports_on_the_hour() {
+        let reports = vec![
+            ("first", vec![report(1.0, 2 * HOUR), report(3.0, 3 * HOUR)]),
+            ("second", vec![report(2.0, 2 * HOUR + 1800), report(5.0, 3 * HOUR + 600), report(6.0, 4 * HOUR)])
         ];
 
-        let average_report = average_forecast_report(reports);
+        let average_report = average_hourly_report(reports, &Aggregation::default());
 
-        assert_eq!(average_report[0].temperature, 2.0);
-        assert_eq!(average_report[0].unix_timestamp, 10);
-        assert_eq!(average_report[1].temperature, 3.0);
-        assert_eq!(average_report[1].unix_timestamp, 33);
+        assert_eq!(average_report.len(), 3);
+        assert_eq!(average_report[0].report.unix_timestamp, 2 * HOUR);
+        assert_eq!(average_report[0].report.temperature, 1.5);
+        assert_eq!(average_report[1].report.temperature, 4.0);
+        assert_eq!(average_report[1].providers, vec!["first", "second"]);
+        assert_eq!(average_report[2].providers, vec!["second"]);
     }
 }
@@ -0,0 +1,216 @@
+use crate::Spread;
+use crate::config::Config;
+use average::Variance;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregationStrategy {
+    Mean,
+    Median,
+    TrimmedMean,
+    WeightedMean
+}
+
+// values which are combined with a strategy, wind direction and condition have their own rules
+pub const AGGREGATED_FIELDS: &[&str] = &[
+    "temperature", "temperature_min", "temperature_max", "temperature_morning", "temperature_evening", "temperature_night",
+    "humidity", "wind_speed", "pressure", "cloud_cover", "precipitation"
+];
+
+impl FromStr for AggregationStrategy {
+    type Err = String;
+
+    fn from_str(strategy: &str) -> Result<Self, Self::Err> {
+        match strategy.to_lowercase().as_str() {
+            "mean" => Ok(AggregationStrategy::Mean),
+            "median" => Ok(AggregationStrategy::Median),
+            "trimmed_mean" => Ok(AggregationStrategy::TrimmedMean),
+            "weighted_mean" => Ok(AggregationStrategy::WeightedMean),
+            _ => Err(String::from("aggregate should be one of mean, median, trimmed_mean or weighted_mean"))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Aggregation {
+    strategy: AggregationStrategy,
+    fields: HashMap<String, AggregationStrategy>,
+    trim_fraction: f64,
+    weights: HashMap<String, f64>
+}
+
+impl Default for Aggregation {
+    fn default() -> Self {
+        Self { strategy: AggregationStrategy::Mean, fields: HashMap::new(), trim_fraction: 0.25, weights: HashMap::new() }
+    }
+}
+
+impl Aggregation {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            strategy: config.aggregation.strategy,
+            fields: config.aggregation.fields.clone().into_iter().collect(),
+            trim_fraction: config.aggregation.trim_fraction,
+            weights: config.providers.iter().map(|(name, provider)| (name.clone(), provider.weight)).collect()
+        }
+    }
+
+    // strategy requested explicitly applies to every field, overriding per field config
+    pub fn with_strategy(mut self, strategy: AggregationStrategy) -> Self {
+        self.strategy = strategy;
+        self.fields.clear();
+        self
+    }
+
+    pub fn weight(&self, provider: &str) -> f64 {
+        self.weights.get(provider).copied().unwrap_or(1.0)
+    }
+
+    pub fn aggregate(&self, field: &str, stats: &ValueStats) -> Option<f64> {
+        let strategy = self.fields.get(field).copied().unwrap_or(self.strategy);
+        stats.aggregate(strategy, self.trim_fraction)
+    }
+}
+
+// every value reported for a single field, together with weight of the provider which reported it
+#[derive(Clone)]
+pub struct ValueStats {
+    values: Vec<(f64, f64)>
+}
+
+impl ValueStats {
+    pub fn new() -> ValueStats {
+        ValueStats { values: vec![] }
+    }
+
+    pub fn add(&mut self, value: f64, weight: f64) {
+        self.values.push((value, weight));
+    }
+
+    pub fn aggregate(&self, strategy: AggregationStrategy, trim_fraction: f64) -> Option<f64> {
+        if self.values.is_empty() {
+            return None;
+        }
+
+        let value = match strategy {
+            AggregationStrategy::Mean => mean(self.values.iter().map(|(value, _)| *value)),
+            AggregationStrategy::Median => {
+                let sorted = self.sorted();
+                let middle = sorted.len() / 2;
+                // usize::is_multiple_of needs rust 1.87
+                #[allow(clippy::manual_is_multiple_of)]
+                let even = sorted.len() % 2 == 0;
+                if even {
+                    (sorted[middle - 1] + sorted[middle]) / 2.0
+                } else {
+                    sorted[middle]
+                }
+            },
+            AggregationStrategy::TrimmedMean => {
+                let sorted = self.sorted();
+                let trimmed = (sorted.len() as f64 * trim_fraction).floor() as usize;
+                mean(sorted[trimmed..sorted.len() - trimmed].iter().copied())
+            },
+            AggregationStrategy::WeightedMean => {
+                let total_weight : f64 = self.values.iter().map(|(_, weight)| weight).sum();
+                self.values.iter().map(|(value, weight)| value * weight).sum::<f64>() / total_weight
+            }
+        };
+        Some(value)
+    }
+
+    pub fn spread(&self) -> Option<Spread> {
+        if self.values.is_empty() {
+            return None;
+        }
+
+        let sorted = self.sorted();
+        let variance : Variance = sorted.iter().copied().collect();
+        Some(Spread {
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            std_dev: variance.population_variance().sqrt(),
+            provider_count: variance.len()
+        })
+    }
+
+    fn sorted(&self) -> Vec<f64> {
+        let mut sorted : Vec<f64> = self.values.iter().map(|(value, _)| *value).collect();
+        sorted.sort_by(|left, right| left.total_cmp(right));
+        sorted
+    }
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let variance : Variance = values.collect();
+    variance.mean()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(values: &[(f64, f64)]) -> ValueStats {
+        let mut stats = ValueStats::new();
+        for (value, weight) in values {
+            stats.add(*value, *weight);
+        }
+        stats
+    }
+
+    #[test]
+    fn aggregates_with_mean_and_median() {
+        let stats = stats(&[(1.0, 1.0), (2.0, 1.0), (9.0, 1.0)]);
+
+        assert_eq!(stats.aggregate(AggregationStrategy::Mean, 0.25), Some(4.0));
+        assert_eq!(stats.aggregate(AggregationStrategy::Median, 0.25), Some(2.0));
+        assert_eq!(ValueStats::new().aggregate(AggregationStrategy::Median, 0.25), None);
+    }
+
+    #[test]
+    fn aggregates_median_of_even_count() {
+        let stats = stats(&[(4.0, 1.0), (1.0, 1.0), (2.0, 1.0), (9.0, 1.0)]);
+
+        assert_eq!(stats.aggregate(AggregationStrategy::Median, 0.25), Some(3.0));
+    }
+
+    #[test]
+    fn aggregates_with_trimmed_mean() {
+        let stats = stats(&[(1.0, 1.0), (2.0, 1.0), (3.0, 1.0), (30.0, 1.0)]);
+
+        assert_eq!(stats.aggregate(AggregationStrategy::TrimmedMean, 0.25), Some(2.5));
+        assert_eq!(stats.aggregate(AggregationStrategy::TrimmedMean, 0.0), Some(9.0));
+    }
+
+    #[test]
+    fn aggregates_with_weighted_mean() {
+        let stats = stats(&[(10.0, 3.0), (20.0, 1.0)]);
+
+        assert_eq!(stats.aggregate(AggregationStrategy::WeightedMean, 0.25), Some(12.5));
+        assert_eq!(stats.aggregate(AggregationStrategy::Mean, 0.25), Some(15.0));
+    }
+
+    #[test]
+    fn overrides_field_strategies_with_requested_one() {
+        let mut config = Config::default();
+        config.aggregation.fields.insert("temperature".to_string(), AggregationStrategy::Median);
+        let stats = stats(&[(1.0, 1.0), (2.0, 1.0), (9.0, 1.0)]);
+
+        let aggregation = Aggregation::from_config(&config);
+        assert_eq!(aggregation.aggregate("temperature", &stats), Some(2.0));
+        assert_eq!(aggregation.aggregate("humidity", &stats), Some(4.0));
+
+        let aggregation = aggregation.with_strategy(AggregationStrategy::Mean);
+        assert_eq!(aggregation.aggregate("temperature", &stats), Some(4.0));
+    }
+
+    #[test]
+    fn parses_strategy_param() {
+        assert_eq!("Median".parse(), Ok(AggregationStrategy::Median));
+        assert_eq!("weighted_mean".parse(), Ok(AggregationStrategy::WeightedMean));
+        assert!("mode".parse::<AggregationStrategy>().is_err());
+    }
+}
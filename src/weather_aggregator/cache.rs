@@ -0,0 +1,286 @@
+use crate::{Location, WeatherReport};
+use crate::errors::ProviderError;
+use super::circuit_breaker::CircuitStatus;
+use super::weather_clients::{Capabilities, WeatherProvider};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheSettings {
+    pub current_ttl: Duration,
+    pub forecast_ttl: Duration,
+    pub max_entries: usize
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self { current_ttl: Duration::from_secs(10 * 60), forecast_ttl: Duration::from_secs(60 * 60), max_entries: 10_000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum QueryKind {
+    Current,
+    Forecast,
+    Hourly,
+    History(NaiveDate)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    provider: &'static str,
+    location: String,
+    kind: QueryKind
+}
+
+struct CacheEntry {
+    stored_at: Instant,
+    // how many days or hours were asked for, providers may return fewer than that
+    requested: usize,
+    reports: Vec<WeatherReport>
+}
+
+pub struct ResponseCache {
+    settings: CacheSettings,
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>
+}
+
+impl ResponseCache {
+    pub fn new(settings: CacheSettings) -> Self {
+        Self { settings, entries: Mutex::new(HashMap::new()) }
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<(usize, Vec<WeatherReport>)> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| entry.stored_at.elapsed() < self.ttl(&key.kind))
+            .map(|entry| (entry.requested, entry.reports.clone()))
+    }
+
+    fn insert(&self, key: CacheKey, requested: usize, reports: Vec<WeatherReport>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|key, entry| entry.stored_at.elapsed() < self.ttl(&key.kind));
+        // locations come from clients, so within a ttl the oldest entries make room for new ones
+        if entries.len() >= self.settings.max_entries && !entries.contains_key(&key) {
+            let oldest = entries.iter().min_by_key(|(_, entry)| entry.stored_at).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key, CacheEntry { stored_at: Instant::now(), requested, reports });
+    }
+
+    fn ttl(&self, kind: &QueryKind) -> Duration {
+        match kind {
+            QueryKind::Current | QueryKind::Hourly => self.settings.current_ttl,
+            QueryKind::Forecast | QueryKind::History(_) => self.settings.forecast_ttl
+        }
+    }
+}
+
+pub struct CachedProvider {
+    provider: Box<dyn WeatherProvider>,
+    cache: Arc<ResponseCache>
+}
+
+impl CachedProvider {
+    pub fn new(provider: Box<dyn WeatherProvider>, cache: Arc<ResponseCache>) -> Self {
+        Self { provider, cache }
+    }
+
+    fn key(&self, location: &Location, kind: QueryKind) -> CacheKey {
+        let location = match location {
+            Location::Coordinates { lat, lon } => format!("{},{}", Location::truncate_coordinate(*lat), Location::truncate_coordinate(*lon)),
+            location => format!("{:?}", location)
+        };
+        CacheKey { provider: self.provider.name(), location, kind }
+    }
+
+    // a longer cached series also answers requests for fewer days or hours, and a series
+    // which came back short of what was asked is all the provider has for any longer request
+    fn cached_series(&self, key: &CacheKey, count: usize) -> Option<Vec<WeatherReport>> {
+        let (requested, mut reports) = self.cache.get(key)?;
+        if reports.len() < count && requested < count {
+            return None;
+        }
+        reports.truncate(count);
+        Some(reports)
+    }
+}
+
+#[async_trait(?Send)]
+impl WeatherProvider for CachedProvider {
+    fn name(&self) -> &'static str {
+        self.provider.name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.provider.capabilities()
+    }
+
+    async fn get_current(&self, location: &Location) -> Result<WeatherReport, ProviderError> {
+        let key = self.key(location, QueryKind::Current);
+        if let Some(report) = self.cache.get(&key).and_then(|(_, reports)| reports.into_iter().next()) {
+            return Ok(report);
+        }
+
+        let report = self.provider.get_current(location).await?;
+        self.cache.insert(key, 1, vec![report.clone()]);
+        Ok(report)
+    }
+
+    async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let key = self.key(location, QueryKind::Forecast);
+        if let Some(reports) = self.cached_series(&key, days_count) {
+            return Ok(reports);
+        }
+
+        let reports = self.provider.get_forecast(location, days_count).await?;
+        self.cache.insert(key, days_count, reports.clone());
+        Ok(reports)
+    }
+
+    async fn get_hourly(&self, location: &Location, hours_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let key = self.key(location, QueryKind::Hourly);
+        if let Some(reports) = self.cached_series(&key, hours_count) {
+            return Ok(reports);
+        }
+
+        let reports = self.provider.get_hourly(location, hours_count).await?;
+        self.cache.insert(key, hours_count, reports.clone());
+        Ok(reports)
+    }
+
+    async fn get_history(&self, location: &Location, date: NaiveDate) -> Result<WeatherReport, ProviderError> {
+        let key = self.key(location, QueryKind::History(date));
+        if let Some(report) = self.cache.get(&key).and_then(|(_, reports)| reports.into_iter().next()) {
+            return Ok(report);
+        }
+
+        let report = self.provider.get_history(location, date).await?;
+        self.cache.insert(key, 1, vec![report.clone()]);
+        Ok(report)
+    }
+
+    fn circuit_status(&self) -> Option<CircuitStatus> {
+        self.provider.circuit_status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingProvider {
+        calls: Arc<AtomicUsize>
+    }
+
+    #[async_trait(?Send)]
+    impl WeatherProvider for CountingProvider {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities { current: true, forecast: true, max_forecast_days: 7, hourly: false, max_forecast_hours: 0, history_days: 0 }
+        }
+
+        async fn get_current(&self, _location: &Location) -> Result<WeatherReport, ProviderError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(WeatherReport { temperature: 1.0, ..Default::default() })
+        }
+
+        async fn get_forecast(&self, _location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![WeatherReport::default(); days_count.min(7)])
+        }
+    }
+
+    fn cached_provider(settings: CacheSettings) -> (CachedProvider, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let provider = Box::new(CountingProvider { calls: calls.clone() });
+        (CachedProvider::new(provider, Arc::new(ResponseCache::new(settings))), calls)
+    }
+
+    fn city(name: &str) -> Location {
+        Location::City { name: name.to_string(), country_code: None }
+    }
+
+    #[actix_rt::test]
+    async fn it_serves_repeated_queries_from_cache() {
+        let (provider, calls) = cached_provider(CacheSettings::default());
+
+        provider.get_current(&city("kazan")).await.unwrap();
+        provider.get_current(&city("kazan")).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        provider.get_current(&city("moscow")).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn it_reuses_longer_forecast_for_shorter_queries() {
+        let (provider, calls) = cached_provider(CacheSettings::default());
+
+        assert_eq!(provider.get_forecast(&city("kazan"), 5).await.unwrap().len(), 5);
+        assert_eq!(provider.get_forecast(&city("kazan"), 2).await.unwrap().len(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(provider.get_forecast(&city("kazan"), 7).await.unwrap().len(), 7);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn it_reuses_forecast_shorter_than_requested() {
+        let (provider, calls) = cached_provider(CacheSettings::default());
+
+        assert_eq!(provider.get_forecast(&city("kazan"), 10).await.unwrap().len(), 7);
+        assert_eq!(provider.get_forecast(&city("kazan"), 10).await.unwrap().len(), 7);
+        assert_eq!(provider.get_forecast(&city("kazan"), 8).await.unwrap().len(), 7);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(provider.get_forecast(&city("kazan"), 12).await.unwrap().len(), 7);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn it_expires_entries_after_ttl() {
+        let settings = CacheSettings { current_ttl: Duration::from_secs(0), ..CacheSettings::default() };
+        let (provider, calls) = cached_provider(settings);
+
+        provider.get_current(&city("kazan")).await.unwrap();
+        provider.get_current(&city("kazan")).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn it_treats_nearby_coordinates_as_one_location() {
+        let (provider, calls) = cached_provider(CacheSettings::default());
+
+        provider.get_current(&Location::Coordinates { lat: 55.79081, lon: 49.11462 }).await.unwrap();
+        provider.get_current(&Location::Coordinates { lat: 55.79089, lon: 49.11468 }).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_rt::test]
+    async fn it_evicts_oldest_entry_when_full() {
+        let (provider, calls) = cached_provider(CacheSettings { max_entries: 2, ..CacheSettings::default() });
+
+        for name in &["kazan", "moscow", "paris"] {
+            provider.get_current(&city(name)).await.unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        provider.get_current(&city("paris")).await.unwrap();
+        provider.get_current(&city("moscow")).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        provider.get_current(&city("kazan")).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+}
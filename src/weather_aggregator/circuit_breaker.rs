@@ -0,0 +1,291 @@
+use crate::{Location, WeatherReport};
+use crate::errors::ProviderError;
+use super::weather_clients::{Capabilities, WeatherProvider};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerSettings {
+    pub failure_threshold: u32,
+    pub open_duration: Duration
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_secs: Option<u64>
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool
+}
+
+// Stops calling a provider after `failure_threshold` transient failures in a row.
+// Once `open_duration` passes a single probe request is let through, its result
+// either closes the circuit or keeps it open for another `open_duration`.
+pub struct CircuitBreakerProvider {
+    provider: Box<dyn WeatherProvider>,
+    settings: BreakerSettings,
+    state: Mutex<BreakerState>
+}
+
+impl CircuitBreakerProvider {
+    pub fn new(provider: Box<dyn WeatherProvider>, settings: BreakerSettings) -> Self {
+        Self { provider, settings, state: Mutex::new(BreakerState::default()) }
+    }
+
+    fn permit(&self) -> Result<Permit<'_>, ProviderError> {
+        let mut state = self.state.lock().unwrap();
+        match state.opened_at {
+            None => Ok(Permit { state: &self.state, probe: false }),
+            Some(opened_at) if !state.probing && opened_at.elapsed() >= self.settings.open_duration => {
+                log::info!("Probing {} provider after circuit was open for {:?}", self.name(), opened_at.elapsed());
+                state.probing = true;
+                Ok(Permit { state: &self.state, probe: true })
+            },
+            Some(_) => Err(ProviderError::CircuitOpen)
+        }
+    }
+
+    fn record<T>(&self, mut permit: Permit<'_>, result: &Result<T, ProviderError>) {
+        permit.probe = false;
+        let mut state = self.state.lock().unwrap();
+        let failed = matches!(result, Err(error) if error.is_transient());
+        if !failed {
+            if state.opened_at.is_some() {
+                log::info!("Circuit for {} provider is closed again", self.name());
+            }
+            *state = BreakerState::default();
+            return;
+        }
+
+        state.consecutive_failures += 1;
+        if state.probing || (state.opened_at.is_none() && state.consecutive_failures >= self.settings.failure_threshold) {
+            log::warn!(
+                "Circuit for {} provider is open after {} consecutive failures, pausing requests for {:?}",
+                self.name(), state.consecutive_failures, self.settings.open_duration
+            );
+            state.opened_at = Some(Instant::now());
+        }
+        state.probing = false;
+    }
+}
+
+// A probe whose request is dropped before finishing never gets recorded,
+// so it gives the probe slot back to let the next request try again.
+struct Permit<'a> {
+    state: &'a Mutex<BreakerState>,
+    probe: bool
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.state.lock().unwrap().probing = false;
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl WeatherProvider for CircuitBreakerProvider {
+    fn name(&self) -> &'static str {
+        self.provider.name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.provider.capabilities()
+    }
+
+    async fn get_current(&self, location: &Location) -> Result<WeatherReport, ProviderError> {
+        let permit = self.permit()?;
+        let result = self.provider.get_current(location).await;
+        self.record(permit, &result);
+        result
+    }
+
+    async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let permit = self.permit()?;
+        let result = self.provider.get_forecast(location, days_count).await;
+        self.record(permit, &result);
+        result
+    }
+
+    async fn get_hourly(&self, location: &Location, hours_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let permit = self.permit()?;
+        let result = self.provider.get_hourly(location, hours_count).await;
+        self.record(permit, &result);
+        result
+    }
+
+    async fn get_history(&self, location: &Location, date: NaiveDate) -> Result<WeatherReport, ProviderError> {
+        let permit = self.permit()?;
+        let result = self.provider.get_history(location, date).await;
+        self.record(permit, &result);
+        result
+    }
+
+    fn circuit_status(&self) -> Option<CircuitStatus> {
+        let state = self.state.lock().unwrap();
+        let status = match state.opened_at {
+            None => CircuitStatus { state: CircuitState::Closed, consecutive_failures: state.consecutive_failures, retry_in_secs: None },
+            Some(opened_at) => {
+                let retry_in = self.settings.open_duration.checked_sub(opened_at.elapsed()).unwrap_or_default();
+                let circuit_state = if state.probing || retry_in.is_zero() {
+                    CircuitState::HalfOpen
+                } else {
+                    CircuitState::Open
+                };
+                CircuitStatus { state: circuit_state, consecutive_failures: state.consecutive_failures, retry_in_secs: Some(retry_in.as_secs()) }
+            }
+        };
+        Some(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    struct FlakyProvider {
+        failing: Arc<AtomicBool>,
+        calls: Arc<AtomicUsize>
+    }
+
+    #[async_trait(?Send)]
+    impl WeatherProvider for FlakyProvider {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities { current: true, forecast: true, max_forecast_days: 7, hourly: false, max_forecast_hours: 0, history_days: 0 }
+        }
+
+        async fn get_current(&self, _location: &Location) -> Result<WeatherReport, ProviderError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.failing.load(Ordering::SeqCst) {
+                Err(ProviderError::Unavailable("503 Service Unavailable".to_string()))
+            } else {
+                Ok(WeatherReport::default())
+            }
+        }
+
+        async fn get_forecast(&self, _location: &Location, _days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+            Err(ProviderError::UnknownLocation)
+        }
+    }
+
+    fn breaker(open_duration: Duration) -> (CircuitBreakerProvider, Arc<AtomicBool>, Arc<AtomicUsize>) {
+        let failing = Arc::new(AtomicBool::new(true));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let provider = Box::new(FlakyProvider { failing: failing.clone(), calls: calls.clone() });
+        let settings = BreakerSettings { failure_threshold: 2, open_duration };
+        (CircuitBreakerProvider::new(provider, settings), failing, calls)
+    }
+
+    fn kazan() -> Location {
+        Location::City { name: "kazan".to_string(), country_code: None }
+    }
+
+    #[actix_rt::test]
+    async fn it_opens_after_consecutive_failures() {
+        let (provider, _, calls) = breaker(Duration::from_secs(60));
+
+        assert!(provider.get_current(&kazan()).await.is_err());
+        assert!(provider.get_current(&kazan()).await.is_err());
+        assert_eq!(provider.circuit_status().unwrap().state, CircuitState::Open);
+
+        assert!(matches!(provider.get_current(&kazan()).await, Err(ProviderError::CircuitOpen)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn it_ignores_failures_caused_by_request() {
+        let (provider, _, _) = breaker(Duration::from_secs(60));
+
+        for _ in 0..3 {
+            assert!(matches!(provider.get_forecast(&kazan(), 3).await, Err(ProviderError::UnknownLocation)));
+        }
+        assert_eq!(provider.circuit_status().unwrap().state, CircuitState::Closed);
+    }
+
+    #[actix_rt::test]
+    async fn it_probes_provider_and_closes_on_success() {
+        let (provider, failing, calls) = breaker(Duration::from_secs(0));
+
+        assert!(provider.get_current(&kazan()).await.is_err());
+        assert!(provider.get_current(&kazan()).await.is_err());
+        assert_eq!(provider.circuit_status().unwrap().state, CircuitState::HalfOpen);
+
+        assert!(matches!(provider.get_current(&kazan()).await, Err(ProviderError::Unavailable(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        failing.store(false, Ordering::SeqCst);
+        assert!(provider.get_current(&kazan()).await.is_ok());
+        assert_eq!(provider.circuit_status().unwrap().state, CircuitState::Closed);
+        assert_eq!(provider.circuit_status().unwrap().consecutive_failures, 0);
+    }
+
+    struct HangingProvider {
+        calls: Arc<AtomicUsize>
+    }
+
+    #[async_trait(?Send)]
+    impl WeatherProvider for HangingProvider {
+        fn name(&self) -> &'static str {
+            "hanging"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities { current: true, forecast: false, max_forecast_days: 0, hourly: false, max_forecast_hours: 0, history_days: 0 }
+        }
+
+        // fails twice to open the circuit, then never answers
+        async fn get_current(&self, _location: &Location) -> Result<WeatherReport, ProviderError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                return Err(ProviderError::Timeout);
+            }
+            actix_rt::time::delay_for(Duration::from_secs(60)).await;
+            Ok(WeatherReport::default())
+        }
+
+        async fn get_forecast(&self, _location: &Location, _days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+            Err(ProviderError::UnknownLocation)
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_lets_another_probe_through_when_probe_is_dropped() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let settings = BreakerSettings { failure_threshold: 2, open_duration: Duration::from_secs(0) };
+        let provider = CircuitBreakerProvider::new(Box::new(HangingProvider { calls: calls.clone() }), settings);
+
+        assert!(provider.get_current(&kazan()).await.is_err());
+        assert!(provider.get_current(&kazan()).await.is_err());
+
+        for _ in 0..2 {
+            let probe = actix_rt::time::timeout(Duration::from_millis(10), provider.get_current(&kazan())).await;
+            assert!(probe.is_err());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert_eq!(provider.circuit_status().unwrap().state, CircuitState::HalfOpen);
+    }
+}
@@ -1,2 +1,327 @@
 pub mod weatherbit;
 pub mod open_weather;
+pub mod open_meteo;
+pub mod nws;
+pub mod met_norway;
+pub mod custom;
+
+use crate::{Location, WeatherReport};
+use crate::errors::ProviderError;
+use super::{Aggregation, AverageWeatherReport};
+use super::circuit_breaker::CircuitStatus;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use rand::Rng;
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_path_to_error::Segment;
+use std::time::Duration;
+
+const SNIPPET_LENGTH : usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Capabilities {
+    pub current: bool,
+    pub forecast: bool,
+    pub max_forecast_days: usize,
+    pub hourly: bool,
+    pub max_forecast_hours: usize,
+    pub history_days: usize
+}
+
+#[async_trait(?Send)]
+pub trait WeatherProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    async fn get_current(&self, location: &Location) -> Result<WeatherReport, ProviderError>;
+
+    async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError>;
+
+    // only called for providers which report `hourly` capability
+    async fn get_hourly(&self, _location: &Location, _hours_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        Err(ProviderError::Unavailable(format!("{} doesn't provide hourly forecast", self.name())))
+    }
+
+    // only called for dates within `history_days` before today
+    async fn get_history(&self, _location: &Location, _date: NaiveDate) -> Result<WeatherReport, ProviderError> {
+        Err(ProviderError::Unavailable(format!("{} doesn't provide historical weather", self.name())))
+    }
+
+    fn circuit_status(&self) -> Option<CircuitStatus> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // bounds all attempts and delays between them together
+    pub deadline: Option<Duration>
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_retries: 0, base_delay: Duration::from_millis(200), max_delay: Duration::from_secs(2), deadline: None }
+    }
+}
+
+impl RetryPolicy {
+    // "full jitter": random delay up to the exponential backoff, so retries from
+    // concurrent requests don't hit a recovering provider at the same moment
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        let millis = backoff.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+}
+
+#[derive(Default)]
+pub struct ProviderRegistry {
+    providers: Vec<Box<dyn WeatherProvider>>
+}
+
+impl ProviderRegistry {
+    pub fn new() -> Self {
+        Self { providers: vec![] }
+    }
+
+    pub fn register(&mut self, provider: Box<dyn WeatherProvider>) -> &mut Self {
+        self.providers.push(provider);
+        self
+    }
+
+    pub fn providers(&self) -> impl Iterator<Item = &dyn WeatherProvider> {
+        self.providers.iter().map(|provider| provider.as_ref())
+    }
+
+    pub fn max_forecast_days(&self) -> usize {
+        self.providers()
+            .filter(|provider| provider.capabilities().forecast)
+            .map(|provider| provider.capabilities().max_forecast_days)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.providers().map(|provider| provider.name()).collect()
+    }
+}
+
+// only for idempotent GETs: retries connection failures, timeouts, 429 and 5xx responses
+pub async fn send_with_retry(request: reqwest::RequestBuilder, policy: &RetryPolicy) -> Result<reqwest::Response, ProviderError> {
+    match policy.deadline {
+        Some(deadline) => actix_rt::time::timeout(deadline, send_with_retries(request, policy))
+            .await
+            .unwrap_or(Err(ProviderError::Timeout)),
+        None => send_with_retries(request, policy).await
+    }
+}
+
+async fn send_with_retries(request: reqwest::RequestBuilder, policy: &RetryPolicy) -> Result<reqwest::Response, ProviderError> {
+    let mut attempt = 0;
+    loop {
+        let retry = match request.try_clone() {
+            Some(retry) => retry,
+            None => return Ok(request.send().await?)
+        };
+
+        let result = retry.send().await;
+        let retryable = match &result {
+            Ok(response) => response.status().is_server_error() || response.status() == StatusCode::TOO_MANY_REQUESTS,
+            Err(error) => error.is_timeout() || error.is_connect() || error.is_request()
+        };
+        if !retryable || attempt >= policy.max_retries {
+            return Ok(result?);
+        }
+
+        actix_rt::time::delay_for(policy.delay(attempt)).await;
+        attempt += 1;
+    }
+}
+
+pub async fn json_from_response<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, ProviderError> {
+    let status = response.status();
+    let body = response.text().await?;
+    match status {
+        status if status.is_success() && status != StatusCode::NO_CONTENT => parse_json(&body),
+        // weatherbit responds with empty 204 when it can't find a location
+        StatusCode::NOT_FOUND | StatusCode::NO_CONTENT => Err(ProviderError::UnknownLocation),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(ProviderError::InvalidApiKey(error_message(&body))),
+        status => Err(ProviderError::Unavailable(match error_message(&body) {
+            Some(message) => format!("unexpected response status {}: {}", status, message),
+            None => format!("unexpected response status {}", status)
+        }))
+    }
+}
+
+// error names the failed value, or the object lacking a field, with a JSON pointer and quotes it:
+// "invalid response: missing field `temp` at /daily/1 near `{"dt":2,"feels_like":1.5}`"
+pub fn parse_json<T: DeserializeOwned>(body: &str) -> Result<T, ProviderError> {
+    let mut deserializer = serde_json::Deserializer::from_str(body);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+        let pointer : String = error.path()
+            .iter()
+            .map(|segment| match segment {
+                Segment::Seq { index } => format!("/{}", index),
+                Segment::Map { key } | Segment::Enum { variant: key } => format!("/{}", key.replace('~', "~0").replace('/', "~1")),
+                Segment::Unknown => String::from("/?")
+            })
+            .collect();
+        let message = without_position(error.inner());
+        let description = match snippet(body, &pointer) {
+            Some(snippet) => format!("invalid response: {} at {} near `{}`", message, display_pointer(&pointer), snippet),
+            None => format!("invalid response: {} at {}", message, display_pointer(&pointer))
+        };
+        ProviderError::Parse(description)
+    })?;
+    deserializer.end().map_err(|error| ProviderError::Parse(format!("invalid response: {}", without_position(&error))))?;
+    Ok(value)
+}
+
+fn display_pointer(pointer: &str) -> &str {
+    if pointer.is_empty() { "/" } else { pointer }
+}
+
+// compact JSON of the value at `pointer`, cut to SNIPPET_LENGTH characters;
+// there is nothing to quote when the body isn't valid JSON at all
+fn snippet(body: &str, pointer: &str) -> Option<String> {
+    let document : serde_json::Value = serde_json::from_str(body).ok()?;
+    let value = serde_json::to_string(document.pointer(pointer)?).ok()?;
+    if value.chars().count() <= SNIPPET_LENGTH {
+        return Some(value);
+    }
+    Some(format!("{}...", value.chars().take(SNIPPET_LENGTH).collect::<String>()))
+}
+
+// serde_json appends "at line N column M", which means little to whoever reads the error
+fn without_position(error: &serde_json::Error) -> String {
+    let message = error.to_string();
+    match message.rfind(" at line ") {
+        Some(position) => message[..position].to_string(),
+        None => message
+    }
+}
+
+// error bodies look like {"message": ...} for openweather, {"error": ...} for weatherbit
+// and problem+json {"detail": ...} for nws
+fn error_message(body: &str) -> Option<String> {
+    let data : serde_json::Value = serde_json::from_str(body).ok()?;
+    ["message", "error", "detail", "title"]
+        .iter()
+        .find_map(|field| data[field].as_str())
+        .map(String::from)
+}
+
+// Folds hourly reports of a single day into a daily one: details are averaged,
+// low and high come from hourly extremes and precipitation is summed up.
+pub fn daily_from_hourly(hours: Vec<WeatherReport>) -> Option<WeatherReport> {
+    let first = hours.first()?.clone();
+    let low = hours.iter().map(|hour| hour.temperature).fold(f64::INFINITY, f64::min);
+    let high = hours.iter().map(|hour| hour.temperature).fold(f64::NEG_INFINITY, f64::max);
+    let precipitation = hours
+        .iter()
+        .filter_map(|hour| hour.precipitation)
+        .fold(None, |total: Option<f64>, amount| Some(total.unwrap_or(0.0) + amount));
+
+    let mut average = AverageWeatherReport::new();
+    for hour in hours {
+        average.add(hour, 1.0);
+    }
+
+    Some(WeatherReport {
+        unix_timestamp: first.unix_timestamp,
+        utc_offset: first.utc_offset,
+        temperature_min: Some(low),
+        temperature_max: Some(high),
+        precipitation,
+        ..average.aggregate(&Aggregation::default())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hour(temperature: f64, unix_timestamp: i64, precipitation: Option<f64>) -> WeatherReport {
+        WeatherReport { temperature, unix_timestamp, utc_offset: 3600, precipitation, ..Default::default() }
+    }
+
+    #[test]
+    fn it_folds_hourly_reports_into_daily() {
+        let daily = daily_from_hourly(vec![
+            hour(1.0, 100, Some(0.5)),
+            hour(5.0, 3700, None),
+            hour(3.0, 7300, Some(1.0))
+        ]).unwrap();
+
+        assert_eq!(daily.temperature, 3.0);
+        assert_eq!(daily.temperature_min, Some(1.0));
+        assert_eq!(daily.temperature_max, Some(5.0));
+        assert_eq!(daily.precipitation, Some(1.5));
+        assert_eq!(daily.unix_timestamp, 100);
+        assert_eq!(daily.utc_offset, 3600);
+
+        assert!(daily_from_hourly(vec![]).is_none());
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug, serde::Deserialize)]
+    struct Forecast {
+        daily: Vec<Day>
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug, serde::Deserialize)]
+    struct Day {
+        dt: i64,
+        temp: f64
+    }
+
+    #[test]
+    fn it_reports_path_of_invalid_field() {
+        let body = r#"{"timezone": "Europe/Moscow", "daily": [{"dt": 1, "temp": 2.5}, {"dt": 2, "temp": "warm"}]}"#;
+
+        let error = parse_json::<Forecast>(body).unwrap_err().to_string();
+
+        assert_eq!(error, "invalid response: invalid type: string \"warm\", expected f64 at /daily/1/temp near `\"warm\"`");
+    }
+
+    #[test]
+    fn it_reports_object_missing_a_field() {
+        let body = "{\n  \"daily\": [\n    {\"dt\": 1, \"temp\": 2.5},\n    {\"dt\": 2, \"feels_like\": 1.5}\n  ]\n}";
+
+        let error = parse_json::<Forecast>(body).unwrap_err().to_string();
+
+        assert_eq!(error, "invalid response: missing field `temp` at /daily/1 near `{\"dt\":2,\"feels_like\":1.5}`");
+        assert_eq!(parse_json::<Forecast>("{}").unwrap_err().to_string(), "invalid response: missing field `daily` at / near `{}`");
+    }
+
+    #[test]
+    fn it_cuts_long_snippets() {
+        let body = format!(r#"{{"daily": [{{"dt": 1, "summary": "{}"}}]}}"#, "x".repeat(200));
+
+        let error = parse_json::<Forecast>(&body).unwrap_err().to_string();
+
+        let snippet = format!("{{\"dt\":1,\"summary\":\"{}...", "x".repeat(SNIPPET_LENGTH - 19));
+        assert_eq!(error, format!("invalid response: missing field `temp` at /daily/0 near `{}`", snippet));
+        assert_eq!(parse_json::<Forecast>("{\"daily\": [").unwrap_err().to_string(), "invalid response: EOF while parsing a list at /daily");
+    }
+
+    #[test]
+    fn it_picks_message_from_provider_error_body() {
+        let open_weather = std::fs::read_to_string("./tests/fixtures/open_weather_invalid_key.json").unwrap();
+        let weatherbit = std::fs::read_to_string("./tests/fixtures/weatherbit_invalid_key.json").unwrap();
+
+        assert_eq!(error_message(&open_weather).unwrap(), "Invalid API key. Please see http://openweathermap.org/faq#error401 for more info.");
+        assert_eq!(error_message(&weatherbit).unwrap(), "API key not valid, or not yet activated.");
+        assert_eq!(error_message("<html>Bad Gateway</html>"), None);
+    }
+}
@@ -0,0 +1,490 @@
+use crate::{Location, WeatherReport};
+use crate::errors::ProviderError;
+use super::{json_from_response, send_with_retry, Capabilities, RetryPolicy, WeatherProvider};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// Provider described in config instead of code: url templates relative to `base_url`
+// and JSON pointers (RFC 6901) to every value of a report.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomProviderDefinition {
+    pub current_url: Option<String>,
+    pub forecast_url: Option<String>,
+    #[serde(default)]
+    pub forecast_items: String,
+    #[serde(default = "default_max_forecast_days")]
+    pub max_forecast_days: usize,
+    pub api_key_header: Option<String>,
+    pub fields: BTreeMap<String, String>,
+    #[serde(default)]
+    pub units: SourceUnits
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceUnits {
+    pub temperature: TemperatureUnit,
+    pub wind_speed: WindSpeedUnit,
+    pub pressure: PressureUnit,
+    pub precipitation: PrecipitationUnit
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+    Kelvin
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum WindSpeedUnit {
+    #[default]
+    #[serde(rename = "m/s")]
+    MetersPerSecond,
+    #[serde(rename = "km/h")]
+    KilometersPerHour,
+    #[serde(rename = "mph")]
+    MilesPerHour,
+    #[serde(rename = "knots")]
+    Knots
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum PressureUnit {
+    #[default]
+    #[serde(rename = "hPa")]
+    Hectopascal,
+    #[serde(rename = "kPa")]
+    Kilopascal,
+    #[serde(rename = "inHg")]
+    InchesOfMercury
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum PrecipitationUnit {
+    #[default]
+    #[serde(rename = "mm")]
+    Millimeters,
+    #[serde(rename = "in")]
+    Inches
+}
+
+// besides report values, `time` is unix seconds or RFC 3339 string, `utc_offset` is in seconds
+// and `timezone` is IANA name which offset is derived from when `utc_offset` is not mapped
+pub const CUSTOM_PROVIDER_FIELDS: &[&str] = &[
+    "time", "utc_offset", "timezone",
+    "temperature", "temperature_min", "temperature_max", "temperature_morning", "temperature_evening", "temperature_night",
+    "humidity", "wind_speed", "wind_direction", "pressure", "cloud_cover", "precipitation"
+];
+const URL_PLACEHOLDERS : &[&str] = &["{lat}", "{lon}", "{city}", "{country}", "{zip}", "{api_key}", "{days}"];
+const LOCATION_PLACEHOLDERS : &[&str] = &["{lat}", "{lon}", "{city}", "{zip}"];
+
+fn default_max_forecast_days() -> usize {
+    7
+}
+
+#[derive(Debug)]
+pub struct CustomProvider {
+    name: &'static str,
+    client: reqwest::Client,
+    api_key: String,
+    api_path_prefix: String,
+    definition: CustomProviderDefinition,
+    timeout: Option<Duration>,
+    retry: RetryPolicy
+}
+
+#[async_trait(?Send)]
+impl WeatherProvider for CustomProvider {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            current: self.definition.current_url.is_some(),
+            forecast: self.definition.forecast_url.is_some(),
+            max_forecast_days: self.definition.max_forecast_days,
+            hourly: false,
+            max_forecast_hours: 0,
+            history_days: 0
+        }
+    }
+
+    async fn get_current(&self, location: &Location) -> Result<WeatherReport, ProviderError> {
+        let template = self.definition.current_url.as_deref()
+            .ok_or_else(|| ProviderError::Unavailable(format!("{} doesn't provide current weather", self.name)))?;
+        let raw_json = self.get_raw(template, location, 1).await?;
+        self.parse_report(&raw_json)
+    }
+
+    async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let template = self.definition.forecast_url.as_deref()
+            .ok_or_else(|| ProviderError::Unavailable(format!("{} doesn't provide forecast", self.name)))?;
+        let raw_json = self.get_raw(template, location, days_count).await?;
+        let items = raw_json
+            .pointer(&self.definition.forecast_items)
+            .and_then(|items| items.as_array())
+            .ok_or_else(|| self.parse_error("array", &self.definition.forecast_items))?;
+        items.iter()
+            .take(days_count)
+            .map(|item| self.parse_report(item))
+            .collect()
+    }
+}
+
+impl CustomProvider {
+    // Provider name comes from config, it is leaked once at startup so custom providers
+    // are named like built-in ones.
+    pub fn new(name: &str, client: reqwest::Client, api_key: String, api_path_prefix: String, definition: CustomProviderDefinition) -> Self {
+        let name = Box::leak(name.to_string().into_boxed_str());
+        Self { name, client, api_key, api_path_prefix, definition, timeout: None, retry: RetryPolicy::default() }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    async fn get_raw(&self, template: &str, location: &Location, days_count: usize) -> Result<serde_json::Value, ProviderError> {
+        let full_path = format!("{}{}", self.api_path_prefix, self.render_url(template, location, days_count)?);
+        let mut request = self.client.get(&full_path);
+        if let Some(header) = &self.definition.api_key_header {
+            request = request.header(header.as_str(), self.api_key.as_str());
+        }
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let response = send_with_retry(request, &self.retry).await?;
+
+        json_from_response(response).await
+    }
+
+    // locations which template has no placeholders for, e.g. city name for coordinates-only url, are not supported
+    fn render_url(&self, template: &str, location: &Location, days_count: usize) -> Result<String, ProviderError> {
+        let mut values = vec![("{api_key}", self.api_key.clone()), ("{days}", days_count.to_string())];
+        match location {
+            Location::Coordinates { lat, lon } => {
+                values.push(("{lat}", lat.to_string()));
+                values.push(("{lon}", lon.to_string()));
+            },
+            Location::City { name, country_code } => {
+                values.push(("{city}", name.clone()));
+                values.push(("{country}", country_code.clone().unwrap_or_default()));
+            },
+            Location::PostalCode { zip, country } => {
+                values.push(("{zip}", zip.clone()));
+                values.push(("{country}", country.clone()));
+            },
+            Location::ProviderCityId { .. } => {}
+        }
+
+        let mut url = template.to_string();
+        for (placeholder, value) in values {
+            url = url.replace(placeholder, &encode(&value));
+        }
+        if LOCATION_PLACEHOLDERS.iter().any(|placeholder| url.contains(placeholder)) {
+            return Err(ProviderError::UnsupportedLocation(location.clone()));
+        }
+        Ok(url.replace("{country}", ""))
+    }
+
+    fn parse_report(&self, data: &serde_json::Value) -> Result<WeatherReport, ProviderError> {
+        let value = |field: &str| self.definition.fields.get(field).and_then(|pointer| data.pointer(pointer));
+        let number = |field: &str| value(field).and_then(|value| value.as_f64());
+        let required = |field: &'static str| self.definition.fields.get(field).map(String::as_str).unwrap_or(field);
+
+        let temperature = number("temperature").ok_or_else(|| self.parse_error("number", required("temperature")))?;
+        let timestamp = value("time")
+            .and_then(|time| time.as_i64().or_else(|| DateTime::parse_from_rfc3339(time.as_str()?).ok().map(|time| time.timestamp())))
+            .ok_or_else(|| self.parse_error("time", required("time")))?;
+        let timezone = value("timezone").and_then(|timezone| timezone.as_str()).map(String::from);
+        let utc_offset = match number("utc_offset") {
+            Some(utc_offset) => utc_offset as i32,
+            None => utc_offset(timezone.as_deref(), timestamp)
+        };
+
+        let units = self.definition.units;
+        let temperature_value = |field: &str| number(field).map(|value| units.temperature.to_celsius(value));
+        Ok(WeatherReport {
+            temperature: units.temperature.to_celsius(temperature),
+            unix_timestamp: timestamp,
+            utc_offset,
+            timezone,
+            temperature_min: temperature_value("temperature_min"),
+            temperature_max: temperature_value("temperature_max"),
+            temperature_morning: temperature_value("temperature_morning"),
+            temperature_evening: temperature_value("temperature_evening"),
+            temperature_night: temperature_value("temperature_night"),
+            humidity: number("humidity"),
+            wind_speed: number("wind_speed").map(|speed| units.wind_speed.to_meters_per_second(speed)),
+            wind_direction: number("wind_direction"),
+            pressure: number("pressure").map(|pressure| units.pressure.to_hectopascals(pressure)),
+            cloud_cover: number("cloud_cover"),
+            precipitation: number("precipitation").map(|precipitation| units.precipitation.to_millimeters(precipitation)),
+            condition: None
+        })
+    }
+
+    fn parse_error(&self, expected: &str, pointer: &str) -> ProviderError {
+        ProviderError::Parse(format!("{} response has no {} at {:?}", self.name, expected, pointer))
+    }
+}
+
+impl CustomProviderDefinition {
+    pub fn needs_api_key(&self) -> bool {
+        self.api_key_header.is_some() || self.current_url.iter().chain(self.forecast_url.iter()).any(|url| url.contains("{api_key}"))
+    }
+
+    pub fn problems(&self, name: &str) -> Vec<String> {
+        let mut problems = vec![];
+        if self.current_url.is_none() && self.forecast_url.is_none() {
+            problems.push(format!("{} should have current_url or forecast_url", name));
+        }
+        for template in self.current_url.iter().chain(self.forecast_url.iter()) {
+            let mut rest = template.clone();
+            for placeholder in URL_PLACEHOLDERS {
+                rest = rest.replace(placeholder, "");
+            }
+            if rest.contains('{') {
+                problems.push(format!("{} url {:?} has unknown placeholder, expected: {}", name, template, URL_PLACEHOLDERS.join(", ")));
+            }
+        }
+        for required in ["temperature", "time"] {
+            if !self.fields.contains_key(required) {
+                problems.push(format!("{} should map {} field", name, required));
+            }
+        }
+        for (field, pointer) in &self.fields {
+            if !CUSTOM_PROVIDER_FIELDS.contains(&field.as_str()) {
+                problems.push(format!("{} maps unknown field {}, expected one of: {}", name, field, CUSTOM_PROVIDER_FIELDS.join(", ")));
+            }
+            if !pointer.starts_with('/') {
+                problems.push(format!("{} field {} should be a JSON pointer like /main/temp, got {:?}", name, field, pointer));
+            }
+        }
+        if !self.forecast_items.is_empty() && !self.forecast_items.starts_with('/') {
+            problems.push(format!("{} forecast_items should be a JSON pointer like /daily, got {:?}", name, self.forecast_items));
+        }
+        problems
+    }
+}
+
+impl TemperatureUnit {
+    fn to_celsius(self, value: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => value,
+            TemperatureUnit::Fahrenheit => (value - 32.0) * 5.0 / 9.0,
+            TemperatureUnit::Kelvin => value - 273.15
+        }
+    }
+}
+
+impl WindSpeedUnit {
+    fn to_meters_per_second(self, value: f64) -> f64 {
+        match self {
+            WindSpeedUnit::MetersPerSecond => value,
+            WindSpeedUnit::KilometersPerHour => value / 3.6,
+            WindSpeedUnit::MilesPerHour => value * 0.44704,
+            WindSpeedUnit::Knots => value * 0.514_444
+        }
+    }
+}
+
+impl PressureUnit {
+    fn to_hectopascals(self, value: f64) -> f64 {
+        match self {
+            PressureUnit::Hectopascal => value,
+            PressureUnit::Kilopascal => value * 10.0,
+            PressureUnit::InchesOfMercury => value * 33.863_89
+        }
+    }
+}
+
+impl PrecipitationUnit {
+    fn to_millimeters(self, value: f64) -> f64 {
+        match self {
+            PrecipitationUnit::Millimeters => value,
+            PrecipitationUnit::Inches => value * 25.4
+        }
+    }
+}
+
+fn utc_offset(timezone: Option<&str>, timestamp: i64) -> i32 {
+    timezone
+        .and_then(|name| name.parse::<Tz>().ok())
+        .map(|tz| {
+            let utc_time = NaiveDateTime::from_timestamp(timestamp, 0);
+            tz.offset_from_utc_datetime(&utc_time).fix().local_minus_utc()
+        })
+        .unwrap_or(0)
+}
+
+// percent-encodes everything except unreserved characters, so city names are safe in path and query
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::MockServer;
+    use httpmock::Method::GET;
+
+    fn definition() -> CustomProviderDefinition {
+        toml::from_str(r#"
+            current_url = "/now?q={city},{country}&key={api_key}"
+            forecast_url = "/points/{lat},{lon}/daily?days={days}"
+            forecast_items = "/forecast/days"
+            max_forecast_days = 5
+
+            [fields]
+            time = "/observed_at"
+            timezone = "/location/tz"
+            temperature = "/temp/avg"
+            temperature_min = "/temp/low"
+            temperature_max = "/temp/high"
+            wind_speed = "/wind/speed"
+            pressure = "/pressure"
+            precipitation = "/rain"
+
+            [units]
+            temperature = "fahrenheit"
+            wind_speed = "km/h"
+            pressure = "inHg"
+            precipitation = "in"
+        "#).unwrap()
+    }
+
+    fn provider(server: &MockServer) -> CustomProvider {
+        CustomProvider::new("regional", reqwest::Client::new(), String::from("secret"), server.url(""), definition())
+    }
+
+    #[test]
+    fn it_validates_definition() {
+        let invalid : CustomProviderDefinition = toml::from_str(r#"
+            current_url = "/now?q={town}"
+            [fields]
+            temperature = "main.temp"
+            visibility = "/visibility"
+        "#).unwrap();
+
+        let problems = invalid.problems("regional");
+        assert_eq!(problems.len(), 4);
+        assert!(problems.iter().any(|problem| problem.contains("unknown placeholder")));
+        assert!(problems.iter().any(|problem| problem.contains("should map time field")));
+        assert!(problems.iter().any(|problem| problem.contains("unknown field visibility")));
+        assert!(problems.iter().any(|problem| problem.contains("JSON pointer like /main/temp")));
+        assert!(definition().problems("regional").is_empty());
+    }
+
+    #[test]
+    fn it_renders_url_templates() {
+        let provider = CustomProvider::new("regional", reqwest::Client::new(), String::from("secret"), String::new(), definition());
+        let city = Location::City { name: "san jose".to_string(), country_code: None };
+
+        assert_eq!(provider.render_url("/now?q={city},{country}&key={api_key}", &city, 1).unwrap(), "/now?q=san%20jose,&key=secret");
+        assert!(matches!(
+            provider.render_url("/points/{lat},{lon}/daily?days={days}", &city, 3),
+            Err(ProviderError::UnsupportedLocation(_))
+        ));
+    }
+
+    #[test]
+    fn it_converts_values_to_metric() {
+        let provider = CustomProvider::new("regional", reqwest::Client::new(), String::new(), String::new(), definition());
+        let data = serde_json::json!({
+            "observed_at": "2021-02-23T12:00:00+03:00",
+            "location": { "tz": "Europe/Moscow" },
+            "temp": { "avg": 212, "low": 32 },
+            "wind": { "speed": 18 },
+            "pressure": 30.0,
+            "rain": 0.5
+        });
+
+        let report = provider.parse_report(&data).unwrap();
+
+        assert_eq!(report.temperature, 100.0);
+        assert_eq!(report.temperature_min, Some(0.0));
+        assert_eq!(report.temperature_max, None);
+        assert_eq!(report.wind_speed, Some(5.0));
+        assert_eq!(report.pressure, Some(30.0 * 33.863_89));
+        assert_eq!(report.precipitation, Some(12.7));
+        assert_eq!(report.unix_timestamp, 1614070800);
+        assert_eq!(report.utc_offset, 10800);
+    }
+
+    #[test]
+    fn it_reports_missing_field_pointer() {
+        let provider = CustomProvider::new("regional", reqwest::Client::new(), String::new(), String::new(), definition());
+
+        let error = provider.parse_report(&serde_json::json!({ "observed_at": 1614070800 })).unwrap_err();
+
+        assert_eq!(error.to_string(), "regional response has no number at \"/temp/avg\"");
+    }
+
+    #[actix_rt::test]
+    async fn it_gets_current_weather_with_api_key() {
+        let server = MockServer::start();
+        let current = server.mock(|when, then| {
+            when.method(GET)
+                .path("/now")
+                .query_param("q", "kazan,RU")
+                .query_param("key", "secret");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{ "observed_at": 1614070800, "temp": { "avg": 5 } }"#);
+        });
+
+        let location = Location::City { name: "kazan".to_string(), country_code: Some("RU".to_string()) };
+        let report = provider(&server).get_current(&location).await.unwrap();
+
+        current.assert_async().await;
+        assert_eq!(report.temperature, (5.0 - 32.0) * 5.0 / 9.0);
+        assert_eq!(report.unix_timestamp, 1614070800);
+    }
+
+    #[actix_rt::test]
+    async fn it_gets_forecast_items() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/points/55.7887,49.1221/daily")
+                .query_param("days", "2");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{ "forecast": { "days": [
+                    { "observed_at": 1614070800, "temp": { "avg": 32, "low": 23, "high": 41 } },
+                    { "observed_at": 1614157200, "temp": { "avg": 50 } },
+                    { "observed_at": 1614243600, "temp": { "avg": 59 } }
+                ] } }"#);
+        });
+
+        let location = Location::Coordinates { lat: 55.7887, lon: 49.1221 };
+        let days = provider(&server).get_forecast(&location, 2).await.unwrap();
+
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].temperature, 0.0);
+        assert_eq!(days[0].temperature_max, Some(5.0));
+        assert_eq!(days[1].temperature, 10.0);
+        assert_eq!(days[1].unix_timestamp, 1614157200);
+    }
+}
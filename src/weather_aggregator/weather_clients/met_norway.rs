@@ -0,0 +1,441 @@
+use crate::{Location, WeatherCondition, WeatherReport};
+use crate::errors::ProviderError;
+use super::{daily_from_hourly, json_from_response, send_with_retry, Capabilities, RetryPolicy, WeatherProvider};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, ACCEPT, EXPIRES, IF_MODIFIED_SINCE, LAST_MODIFIED, USER_AGENT};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// api.met.no terms require an identifying User-Agent, coordinates with at most 4 decimals
+// and not downloading the same forecast again before it expires
+#[derive(Debug)]
+pub struct MetNorway {
+    client: reqwest::Client,
+    user_agent: String,
+    api_path_prefix: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    responses: Mutex<HashMap<String, CachedResponse>>
+}
+
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    data: CompactResponse,
+    last_modified: Option<String>,
+    expires: Option<DateTime<Utc>>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CompactResponse {
+    properties: CompactProperties
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CompactProperties {
+    timeseries: Vec<TimeseriesEntry>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TimeseriesEntry {
+    time: String,
+    data: EntryData
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EntryData {
+    instant: InstantData,
+    next_1_hours: Option<NextPeriod>,
+    next_6_hours: Option<NextPeriod>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct InstantData {
+    details: InstantDetails
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct InstantDetails {
+    air_temperature: f64,
+    relative_humidity: Option<f64>,
+    wind_speed: Option<f64>,
+    wind_from_direction: Option<f64>,
+    air_pressure_at_sea_level: Option<f64>,
+    cloud_area_fraction: Option<f64>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct NextPeriod {
+    summary: Option<Summary>,
+    details: Option<NextDetails>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Summary {
+    symbol_code: String
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct NextDetails {
+    precipitation_amount: Option<f64>
+}
+
+const API_PATH_PREFIX : &str = "https://api.met.no/weatherapi/locationforecast/2.0";
+const MAX_FORECAST_DAYS : usize = 9;
+// timeseries switches from 1-hour to 6-hour steps after about two and a half days
+const MAX_FORECAST_HOURS : usize = 48;
+
+#[async_trait(?Send)]
+impl WeatherProvider for MetNorway {
+    fn name(&self) -> &'static str {
+        "met_norway"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            current: true,
+            forecast: true,
+            max_forecast_days: MAX_FORECAST_DAYS,
+            hourly: true,
+            max_forecast_hours: MAX_FORECAST_HOURS,
+            history_days: 0
+        }
+    }
+
+    // timeseries starts with the current hour
+    async fn get_current(&self, location: &Location) -> Result<WeatherReport, ProviderError> {
+        let hours = self.get_timeseries(location).await?;
+        hours.into_iter()
+            .next()
+            .ok_or_else(|| ProviderError::Parse(String::from("invalid response: no entries at /properties/timeseries")))
+    }
+
+    async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let hours = self.get_timeseries(location).await?;
+        let mut days = Self::daily_from_timeseries(hours);
+        days.truncate(days_count);
+        Ok(days)
+    }
+
+    async fn get_hourly(&self, location: &Location, hours_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let mut hours = self.get_timeseries(location).await?;
+        hours.truncate(hours_count);
+        Ok(hours)
+    }
+}
+
+impl MetNorway {
+    pub fn new(client: reqwest::Client, user_agent: String) -> Self {
+        Self::new_with_prefix(client, user_agent, API_PATH_PREFIX.to_string())
+    }
+
+    pub fn new_with_prefix(client: reqwest::Client, user_agent: String, api_path_prefix: String) -> Self {
+        Self { client, user_agent, api_path_prefix, timeout: None, retry: RetryPolicy::default(), responses: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    async fn get_timeseries(&self, location: &Location) -> Result<Vec<WeatherReport>, ProviderError> {
+        let (lat, lon) = match location {
+            // the api rejects coordinates with more than 4 decimals
+            Location::Coordinates { lat, lon } => (Location::truncate_coordinate(*lat), Location::truncate_coordinate(*lon)),
+            _ => return Err(ProviderError::UnsupportedLocation(location.clone()))
+        };
+        let response = self.get_raw(&lat, &lon).await?;
+        Self::reports_from_timeseries(response, lon.parse().unwrap_or(0.0))
+    }
+
+    // Forecast is reused until `Expires`, after that it is asked for with `If-Modified-Since`
+    // and 304 answer means the cached one is still the latest.
+    async fn get_raw(&self, lat: &str, lon: &str) -> Result<CompactResponse, ProviderError> {
+        let key = format!("{},{}", lat, lon);
+        let cached = self.responses.lock().unwrap().get(&key).cloned();
+        if let Some(cached) = &cached {
+            if cached.expires.is_some_and(|expires| expires > Utc::now()) {
+                return Ok(cached.data.clone());
+            }
+        }
+
+        let full_path = format!("{}/compact", self.api_path_prefix);
+        let mut request = self.client
+            .get(&full_path)
+            .query(&[("lat", lat), ("lon", lon)])
+            .header(USER_AGENT, self.user_agent.as_str())
+            .header(ACCEPT, "application/json");
+        if let Some(last_modified) = cached.as_ref().and_then(|cached| cached.last_modified.as_ref()) {
+            request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+        }
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let response = send_with_retry(request, &self.retry).await?;
+
+        let headers = response.headers().clone();
+        let data = match (response.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some(cached)) => cached.data,
+            // met.no answers 403 to requests without proper User-Agent, there is no api key to blame
+            (StatusCode::FORBIDDEN, _) =>
+                return Err(ProviderError::Unavailable(String::from("request was forbidden, check that user_agent identifies the app"))),
+            _ => json_from_response(response).await?
+        };
+        // expired forecasts of other locations would only save a download on revalidation
+        let mut responses = self.responses.lock().unwrap();
+        let now = Utc::now();
+        responses.retain(|_, cached| cached.expires.is_some_and(|expires| expires > now));
+        responses.insert(key, Self::cached_response(data.clone(), &headers));
+        Ok(data)
+    }
+
+    fn cached_response(data: CompactResponse, headers: &HeaderMap) -> CachedResponse {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        CachedResponse {
+            data,
+            last_modified: header(LAST_MODIFIED).map(String::from),
+            expires: header(EXPIRES)
+                .and_then(|expires| DateTime::parse_from_rfc2822(expires).ok())
+                .map(|expires| expires.with_timezone(&Utc))
+        }
+    }
+
+    // Timeseries is in UTC and the api doesn't know location timezone, so local time
+    // is approximated by longitude. Each entry becomes an hourly report.
+    fn reports_from_timeseries(response: CompactResponse, lon: f64) -> Result<Vec<WeatherReport>, ProviderError> {
+        let utc_offset = (lon / 15.0).round() as i32 * 3600;
+        response.properties.timeseries
+            .into_iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let time = DateTime::parse_from_rfc3339(&entry.time).map_err(|_| ProviderError::Parse(format!(
+                    "invalid response: invalid date {:?} at /properties/timeseries/{}/time", entry.time, index
+                )))?;
+                let details = entry.data.instant.details;
+                // precipitation and symbol cover the time until the next entry, an hour or six
+                let next = entry.data.next_1_hours.or(entry.data.next_6_hours);
+                Ok(WeatherReport {
+                    temperature: details.air_temperature,
+                    unix_timestamp: time.timestamp(),
+                    utc_offset,
+                    humidity: details.relative_humidity,
+                    wind_speed: details.wind_speed,
+                    wind_direction: details.wind_from_direction,
+                    pressure: details.air_pressure_at_sea_level,
+                    cloud_cover: details.cloud_area_fraction,
+                    precipitation: next.as_ref().and_then(|next| next.details.as_ref()).and_then(|details| details.precipitation_amount),
+                    condition: next.and_then(|next| next.summary).and_then(|summary| Self::parse_condition(&summary.symbol_code)),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn daily_from_timeseries(hours: Vec<WeatherReport>) -> Vec<WeatherReport> {
+        let mut days : BTreeMap<NaiveDate, Vec<WeatherReport>> = BTreeMap::new();
+        for hour in hours {
+            days.entry(hour.local_date()).or_default().push(hour);
+        }
+        days.into_values().filter_map(daily_from_hourly).collect()
+    }
+
+    // symbol codes like "lightrainshowers_day" are translated to openweather conditions,
+    // so conditions of all providers can be compared
+    fn parse_condition(symbol_code: &str) -> Option<WeatherCondition> {
+        let symbol = symbol_code.split('_').next()?;
+        let (code, description) = match symbol {
+            "clearsky" => (800, "clear sky"),
+            "fair" => (801, "few clouds"),
+            "partlycloudy" => (802, "scattered clouds"),
+            "cloudy" => (804, "overcast clouds"),
+            "fog" => (741, "fog"),
+            "lightrain" => (500, "light rain"),
+            "rain" => (501, "moderate rain"),
+            "heavyrain" => (502, "heavy intensity rain"),
+            "lightrainshowers" => (520, "light intensity shower rain"),
+            "rainshowers" => (521, "shower rain"),
+            "heavyrainshowers" => (522, "heavy intensity shower rain"),
+            "lightsleet" | "sleet" | "heavysleet" | "lightsleetshowers" | "sleetshowers" | "heavysleetshowers" => (611, "sleet"),
+            "lightsnow" => (600, "light snow"),
+            "snow" => (601, "snow"),
+            "heavysnow" => (602, "heavy snow"),
+            "lightsnowshowers" => (620, "light shower snow"),
+            "snowshowers" => (621, "shower snow"),
+            "heavysnowshowers" => (622, "heavy shower snow"),
+            symbol if symbol.contains("thunder") => (211, "thunderstorm"),
+            _ => return None
+        };
+        Some(WeatherCondition { code, description: description.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weather_aggregator::weather_clients::parse_json;
+    use httpmock::MockServer;
+    use httpmock::Method::GET;
+
+    const USER_AGENT_VALUE : &str = "weather-reports-test (ops@example.com)";
+    const LAST_MODIFIED_VALUE : &str = "Tue, 23 Feb 2021 10:51:23 GMT";
+
+    fn oslo() -> Location {
+        Location::Coordinates { lat: 59.91273891, lon: 10.74609876 }
+    }
+
+    fn met_norway(server: &MockServer) -> MetNorway {
+        MetNorway::new_with_prefix(reqwest::Client::new(), USER_AGENT_VALUE.to_string(), server.url(""))
+    }
+
+    fn mock_compact<'a>(server: &'a MockServer, expires: &str) -> httpmock::MockRef<'a> {
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/compact")
+                .query_param("lat", "59.9127")
+                .query_param("lon", "10.7460")
+                .header("User-Agent", USER_AGENT_VALUE);
+
+            let json = std::fs::read_to_string("./tests/fixtures/met_norway_compact_success.json").unwrap();
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .header("Last-Modified", LAST_MODIFIED_VALUE)
+                .header("Expires", expires)
+                .body(json);
+        })
+    }
+
+    #[test]
+    fn it_folds_timeseries_into_days() {
+        let raw_json = std::fs::read_to_string("./tests/fixtures/met_norway_compact_success.json").unwrap();
+
+        let hours = parse_json(&raw_json).and_then(|response| MetNorway::reports_from_timeseries(response, 10.746)).unwrap();
+        assert_eq!(hours[0].temperature, -3.2);
+        assert_eq!(hours[0].utc_offset, 3600);
+        assert_eq!(hours[0].precipitation, Some(0.0));
+        assert_eq!(hours[0].condition.as_ref().map(|condition| condition.code), Some(802));
+        assert_eq!(hours[3].precipitation, Some(1.2));
+
+        let days = MetNorway::daily_from_timeseries(hours);
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].local_date(), NaiveDate::from_ymd(2021, 2, 23));
+        assert_eq!(days[0].temperature_min, Some(-3.2));
+        assert_eq!(days[0].temperature_max, Some(-1.0));
+        assert_eq!(days[1].local_date(), NaiveDate::from_ymd(2021, 2, 24));
+        assert_eq!(days[1].precipitation, Some(1.2));
+    }
+
+    #[test]
+    fn it_fails_on_entry_without_air_temperature() {
+        let raw_json = r#"
+        {
+            "properties": {
+                "timeseries": [
+                    { "time": "2021-02-23T12:00:00Z", "data": { "instant": { "details": { "air_pressure_at_sea_level": 1012.3 } } } }
+                ]
+            }
+        }
+        "#;
+
+        let error = parse_json(raw_json).and_then(|response| MetNorway::reports_from_timeseries(response, 10.746)).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "invalid response: missing field `air_temperature` at /properties/timeseries/0/data/instant/details near `{\"air_pressure_at_sea_level\":1012.3}`"
+        );
+    }
+
+    #[test]
+    fn it_parses_symbol_codes() {
+        assert_eq!(MetNorway::parse_condition("clearsky_night").map(|condition| condition.code), Some(800));
+        assert_eq!(MetNorway::parse_condition("heavyrainshowersandthunder_day").map(|condition| condition.code), Some(211));
+        assert!(MetNorway::parse_condition("unknown").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn it_reuses_forecast_until_it_expires() {
+        let server = MockServer::start();
+        let compact = mock_compact(&server, "Mon, 01 Jan 2120 00:00:00 GMT");
+
+        let provider = met_norway(&server);
+        let report = provider.get_current(&oslo()).await.unwrap();
+        provider.get_forecast(&oslo(), 2).await.unwrap();
+
+        assert_eq!(report.temperature, -3.2);
+        assert_eq!(report.pressure, Some(1021.3));
+        compact.assert_hits_async(1).await;
+    }
+
+    #[actix_rt::test]
+    async fn it_revalidates_expired_forecast_with_if_modified_since() {
+        let server = MockServer::start();
+        let compact = mock_compact(&server, "Tue, 23 Feb 2021 11:20:00 GMT");
+
+        let provider = met_norway(&server);
+        provider.get_current(&oslo()).await.unwrap();
+        compact.assert_hits_async(1).await;
+        compact.delete_async().await;
+
+        let not_modified = server.mock(|when, then| {
+            when.method(GET)
+                .path("/compact")
+                .header("If-Modified-Since", LAST_MODIFIED_VALUE);
+            then.status(304);
+        });
+        let hours = provider.get_hourly(&oslo(), 2).await.unwrap();
+
+        assert_eq!(hours.len(), 2);
+        assert_eq!(hours[1].temperature, -2.5);
+        not_modified.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn it_drops_expired_forecasts_of_other_locations() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/compact");
+
+            let json = std::fs::read_to_string("./tests/fixtures/met_norway_compact_success.json").unwrap();
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .header("Expires", "Tue, 23 Feb 2021 11:20:00 GMT")
+                .body(json);
+        });
+
+        let provider = met_norway(&server);
+        provider.get_current(&oslo()).await.unwrap();
+        provider.get_current(&Location::Coordinates { lat: 60.39, lon: 5.32 }).await.unwrap();
+
+        assert_eq!(provider.responses.lock().unwrap().keys().collect::<Vec<_>>(), vec!["60.3900,5.3200"]);
+    }
+
+    #[actix_rt::test]
+    async fn it_explains_forbidden_response() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/compact");
+            then.status(403);
+        });
+
+        let report = met_norway(&server).get_current(&oslo()).await;
+
+        assert!(matches!(report, Err(ProviderError::Unavailable(message)) if message.contains("user_agent")));
+    }
+
+    #[actix_rt::test]
+    async fn it_skips_locations_without_coordinates() {
+        let location = Location::City { name: "oslo".to_string(), country_code: Some("NO".to_string()) };
+        let report = MetNorway::new(reqwest::Client::new(), USER_AGENT_VALUE.to_string()).get_forecast(&location, 3).await;
+
+        assert!(matches!(report, Err(ProviderError::UnsupportedLocation(_))));
+    }
+}
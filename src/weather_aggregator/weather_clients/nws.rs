@@ -0,0 +1,454 @@
+use crate::{Location, WeatherReport};
+use crate::errors::ProviderError;
+use super::{json_from_response, send_with_retry, Capabilities, RetryPolicy, WeatherProvider};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use reqwest::header::{ACCEPT, USER_AGENT};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// api.weather.gov rejects requests without User-Agent, so it is set on every request
+// instead of relying on client defaults
+#[derive(Debug)]
+pub struct NationalWeatherService {
+    client: reqwest::Client,
+    user_agent: String,
+    api_path_prefix: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    // last use of each grid point, the least recently used one is dropped when full
+    grid_points: Mutex<HashMap<String, (Instant, GridPoint)>>,
+    max_grid_points: usize
+}
+
+#[derive(Debug, Clone)]
+struct GridPoint {
+    forecast_url: String,
+    forecast_hourly_url: String,
+    timezone: Option<String>
+}
+
+// a single forecast period: 12-hour day or night in daily forecast, an hour in hourly one
+#[derive(Debug)]
+struct Period {
+    start: DateTime<FixedOffset>,
+    is_daytime: bool,
+    temperature: f64,
+    humidity: Option<f64>,
+    wind_speed: Option<f64>,
+    wind_direction: Option<f64>
+}
+
+#[derive(Deserialize)]
+struct PointsResponse {
+    properties: PointProperties
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PointProperties {
+    forecast: String,
+    forecast_hourly: String,
+    time_zone: Option<String>
+}
+
+#[derive(Deserialize)]
+struct ForecastResponse {
+    properties: ForecastProperties
+}
+
+#[derive(Deserialize)]
+struct ForecastProperties {
+    periods: Vec<ForecastPeriod>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ForecastPeriod {
+    start_time: String,
+    is_daytime: Option<bool>,
+    temperature: f64,
+    temperature_unit: Option<String>,
+    relative_humidity: Option<Measurement>,
+    wind_speed: Option<String>,
+    wind_direction: Option<String>
+}
+
+#[derive(Deserialize)]
+struct Measurement {
+    value: Option<f64>
+}
+
+const API_PATH_PREFIX : &str = "https://api.weather.gov";
+const MAX_FORECAST_DAYS : usize = 7;
+const MAX_FORECAST_HOURS : usize = 156;
+const MAX_GRID_POINTS : usize = 1000;
+const COMPASS_POINTS : [&str; 16] = ["N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW", "NNW"];
+
+#[async_trait(?Send)]
+impl WeatherProvider for NationalWeatherService {
+    fn name(&self) -> &'static str {
+        "nws"
+    }
+
+    // nws publishes observations per station, not per point, so there is no current weather for a location
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            current: false,
+            forecast: true,
+            max_forecast_days: MAX_FORECAST_DAYS,
+            hourly: true,
+            max_forecast_hours: MAX_FORECAST_HOURS,
+            history_days: 0
+        }
+    }
+
+    async fn get_current(&self, _location: &Location) -> Result<WeatherReport, ProviderError> {
+        Err(ProviderError::Unavailable(format!("{} doesn't provide current weather", self.name())))
+    }
+
+    async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let grid_point = self.grid_point(location).await?;
+        let response = self.get_raw(&grid_point.forecast_url).await?;
+        let periods = Self::periods_from_response(response)?;
+        let mut days = Self::daily_from_periods(periods, grid_point.timezone.as_deref());
+        days.truncate(days_count);
+        Ok(days)
+    }
+
+    async fn get_hourly(&self, location: &Location, hours_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let grid_point = self.grid_point(location).await?;
+        let response = self.get_raw(&grid_point.forecast_hourly_url).await?;
+        let periods = Self::periods_from_response(response)?;
+        Ok(periods
+            .iter()
+            .take(hours_count)
+            .map(|period| Self::report_from_periods(&[period], grid_point.timezone.as_deref()))
+            .collect())
+    }
+}
+
+impl NationalWeatherService {
+    pub fn new(client: reqwest::Client, user_agent: String) -> Self {
+        Self::new_with_prefix(client, user_agent, API_PATH_PREFIX.to_string())
+    }
+
+    pub fn new_with_prefix(client: reqwest::Client, user_agent: String, api_path_prefix: String) -> Self {
+        Self { client, user_agent, api_path_prefix, timeout: None, retry: RetryPolicy::default(), grid_points: Mutex::new(HashMap::new()), max_grid_points: MAX_GRID_POINTS }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    // Forecasts are published per 2.5km grid cell, so coordinates are looked up in /points first.
+    // Grid cells don't move, so lookups are kept for the lifetime of the app, up to `max_grid_points` of them.
+    async fn grid_point(&self, location: &Location) -> Result<GridPoint, ProviderError> {
+        let point = match location {
+            Location::Coordinates { lat, lon } => format!("{:.4},{:.4}", lat, lon),
+            _ => return Err(ProviderError::UnsupportedLocation(location.clone()))
+        };
+        if let Some((last_used, grid_point)) = self.grid_points.lock().unwrap().get_mut(&point) {
+            *last_used = Instant::now();
+            return Ok(grid_point.clone());
+        }
+
+        let response = self.get_raw(&format!("{}/points/{}", self.api_path_prefix, point)).await?;
+        let grid_point = Self::grid_point_from_response(response);
+        let mut grid_points = self.grid_points.lock().unwrap();
+        if grid_points.len() >= self.max_grid_points {
+            let least_recent = grid_points.iter().min_by_key(|(_, (last_used, _))| *last_used).map(|(point, _)| point.clone());
+            if let Some(least_recent) = least_recent {
+                grid_points.remove(&least_recent);
+            }
+        }
+        grid_points.insert(point, (Instant::now(), grid_point.clone()));
+        Ok(grid_point)
+    }
+
+    async fn get_raw<T: DeserializeOwned>(&self, url: &str) -> Result<T, ProviderError> {
+        let mut request = self.client
+            .get(url)
+            .query(&[("units", "si")])
+            .header(USER_AGENT, self.user_agent.as_str())
+            .header(ACCEPT, "application/geo+json");
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let response = send_with_retry(request, &self.retry).await?;
+
+        json_from_response(response).await
+    }
+
+    fn grid_point_from_response(response: PointsResponse) -> GridPoint {
+        let properties = response.properties;
+        GridPoint {
+            forecast_url: properties.forecast,
+            forecast_hourly_url: properties.forecast_hourly,
+            timezone: properties.time_zone
+        }
+    }
+
+    fn periods_from_response(response: ForecastResponse) -> Result<Vec<Period>, ProviderError> {
+        response.properties.periods
+            .into_iter()
+            .enumerate()
+            .map(|(index, period)| {
+                let start = DateTime::parse_from_rfc3339(&period.start_time).map_err(|_| ProviderError::Parse(format!(
+                    "invalid response: invalid date {:?} at /properties/periods/{}/startTime", period.start_time, index
+                )))?;
+                let temperature = match period.temperature_unit.as_deref() {
+                    Some("F") => (period.temperature - 32.0) * 5.0 / 9.0,
+                    _ => period.temperature
+                };
+                Ok(Period {
+                    start,
+                    is_daytime: period.is_daytime.unwrap_or(true),
+                    temperature,
+                    humidity: period.relative_humidity.and_then(|humidity| humidity.value),
+                    wind_speed: period.wind_speed.as_deref().and_then(Self::parse_wind_speed),
+                    wind_direction: period.wind_direction.as_deref().and_then(Self::parse_wind_direction)
+                })
+            })
+            .collect()
+    }
+
+    // Daily forecast alternates 12-hour day and night periods. Periods are grouped by local
+    // date they start on: day period gives the high and night period gives the low.
+    fn daily_from_periods(periods: Vec<Period>, timezone: Option<&str>) -> Vec<WeatherReport> {
+        let mut days : BTreeMap<NaiveDate, Vec<&Period>> = BTreeMap::new();
+        for period in &periods {
+            days.entry(period.start.naive_local().date()).or_default().push(period);
+        }
+
+        days.into_values()
+            .map(|day_periods| {
+                let high = day_periods.iter().find(|period| period.is_daytime).map(|period| period.temperature);
+                let low = day_periods.iter().find(|period| !period.is_daytime).map(|period| period.temperature);
+                WeatherReport {
+                    temperature_min: low,
+                    temperature_max: high,
+                    temperature_night: low,
+                    ..Self::report_from_periods(&day_periods, timezone)
+                }
+            })
+            .collect()
+    }
+
+    fn report_from_periods(periods: &[&Period], timezone: Option<&str>) -> WeatherReport {
+        let first = periods[0];
+        let mean = |values: Vec<f64>| if values.is_empty() { None } else { Some(values.iter().sum::<f64>() / values.len() as f64) };
+        WeatherReport {
+            temperature: mean(periods.iter().map(|period| period.temperature).collect()).unwrap_or(first.temperature),
+            unix_timestamp: first.start.timestamp(),
+            utc_offset: first.start.offset().local_minus_utc(),
+            timezone: timezone.map(String::from),
+            humidity: mean(periods.iter().filter_map(|period| period.humidity).collect()),
+            wind_speed: mean(periods.iter().filter_map(|period| period.wind_speed).collect()),
+            wind_direction: periods.iter().find_map(|period| period.wind_direction),
+            ..Default::default()
+        }
+    }
+
+    // "10 mph", "5 to 10 km/h": ranges are reduced to their middle, result is in m/s
+    fn parse_wind_speed(wind_speed: &str) -> Option<f64> {
+        let speeds : Vec<f64> = wind_speed.split_whitespace().filter_map(|word| word.parse().ok()).collect();
+        if speeds.is_empty() {
+            return None;
+        }
+        let speed = speeds.iter().sum::<f64>() / speeds.len() as f64;
+        if wind_speed.contains("mph") {
+            Some(speed * 0.44704)
+        } else {
+            Some(speed / 3.6)
+        }
+    }
+
+    fn parse_wind_direction(direction: &str) -> Option<f64> {
+        COMPASS_POINTS
+            .iter()
+            .position(|point| *point == direction)
+            .map(|index| index as f64 * 22.5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weather_aggregator::weather_clients::parse_json;
+    use httpmock::MockServer;
+    use httpmock::Method::GET;
+
+    const USER_AGENT_VALUE : &str = "weather-reports-test (ops@example.com)";
+
+    fn topeka() -> Location {
+        Location::Coordinates { lat: 39.74561234, lon: -97.08949876 }
+    }
+
+    fn nws(server: &MockServer) -> NationalWeatherService {
+        NationalWeatherService::new_with_prefix(reqwest::Client::new(), USER_AGENT_VALUE.to_string(), server.url(""))
+    }
+
+    fn mock_points(server: &MockServer) -> httpmock::MockRef<'_> {
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/points/39.7456,-97.0895")
+                .header("User-Agent", USER_AGENT_VALUE);
+
+            let json = std::fs::read_to_string("./tests/fixtures/nws_points_success.json")
+                .unwrap()
+                .replace("https://api.weather.gov", &server.url(""));
+            then.status(200)
+                .header("Content-Type", "application/geo+json")
+                .body(json);
+        })
+    }
+
+    #[test]
+    fn it_merges_day_and_night_periods_into_days() {
+        let raw_json = r#"
+        {
+            "properties": {
+                "periods": [
+                    { "startTime": "2021-02-23T18:00:00-06:00", "isDaytime": false, "temperature": -4, "temperatureUnit": "C", "windSpeed": "10 km/h", "windDirection": "N" },
+                    { "startTime": "2021-02-24T06:00:00-06:00", "isDaytime": true, "temperature": 8, "temperatureUnit": "C", "windSpeed": "10 to 20 km/h", "windDirection": "S" },
+                    { "startTime": "2021-02-24T18:00:00-06:00", "isDaytime": false, "temperature": 41, "temperatureUnit": "F", "windSpeed": "5 mph", "windDirection": "SW" }
+                ]
+            }
+        }
+        "#;
+        let periods = parse_json(raw_json).and_then(NationalWeatherService::periods_from_response).unwrap();
+        let days = NationalWeatherService::daily_from_periods(periods, Some("America/Chicago"));
+
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].local_date(), NaiveDate::from_ymd(2021, 2, 23));
+        assert_eq!(days[0].temperature_max, None);
+        assert_eq!(days[0].temperature_min, Some(-4.0));
+        assert_eq!(days[1].local_date(), NaiveDate::from_ymd(2021, 2, 24));
+        assert_eq!(days[1].utc_offset, -21600);
+        assert_eq!(days[1].temperature_max, Some(8.0));
+        assert_eq!(days[1].temperature_min, Some(5.0));
+        assert_eq!(days[1].temperature, 6.5);
+        assert_eq!(days[1].wind_direction, Some(180.0));
+        assert_eq!(days[1].timezone.as_deref(), Some("America/Chicago"));
+    }
+
+    #[test]
+    fn it_fails_on_mistyped_period_temperature() {
+        let raw_json = r#"
+        {
+            "properties": {
+                "periods": [
+                    { "startTime": "2021-02-24T06:00:00-06:00", "isDaytime": true, "temperature": "8", "temperatureUnit": "C" }
+                ]
+            }
+        }
+        "#;
+
+        let error = parse_json(raw_json).and_then(NationalWeatherService::periods_from_response).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "invalid response: invalid type: string \"8\", expected f64 at /properties/periods/0/temperature near `\"8\"`"
+        );
+    }
+
+    #[test]
+    fn it_parses_wind() {
+        assert_eq!(NationalWeatherService::parse_wind_speed("18 km/h"), Some(5.0));
+        assert_eq!(NationalWeatherService::parse_wind_speed("5 to 15 km/h"), Some(10.0 / 3.6));
+        assert_eq!(NationalWeatherService::parse_wind_speed("calm"), None);
+        assert_eq!(NationalWeatherService::parse_wind_direction("WNW"), Some(292.5));
+        assert_eq!(NationalWeatherService::parse_wind_direction(""), None);
+    }
+
+    #[actix_rt::test]
+    async fn it_looks_up_grid_point_and_fetches_its_forecast() {
+        let server = MockServer::start();
+        let points = mock_points(&server);
+        let forecast = server.mock(|when, then| {
+            when.method(GET)
+                .path("/gridpoints/TOP/31,80/forecast")
+                .query_param("units", "si")
+                .header("User-Agent", USER_AGENT_VALUE);
+
+            let json = std::fs::read_to_string("./tests/fixtures/nws_forecast_success.json").unwrap();
+            then.status(200)
+                .header("Content-Type", "application/geo+json")
+                .body(json);
+        });
+
+        let provider = nws(&server);
+        let days = provider.get_forecast(&topeka(), 2).await.unwrap();
+        provider.get_forecast(&topeka(), 2).await.unwrap();
+
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].temperature_max, Some(7.2));
+        assert_eq!(days[0].temperature_min, Some(-3.9));
+        assert_eq!(days[0].humidity, Some(60.0));
+        assert_eq!(days[0].timezone.as_deref(), Some("America/Chicago"));
+        assert_eq!(days[1].local_date(), NaiveDate::from_ymd(2021, 2, 24));
+        points.assert_hits_async(1).await;
+        forecast.assert_hits_async(2).await;
+    }
+
+    #[actix_rt::test]
+    async fn it_drops_least_recently_used_grid_point_when_full() {
+        let server = MockServer::start();
+        let points = server.mock(|when, then| {
+            when.method(GET)
+                .path_contains("/points/");
+
+            let json = std::fs::read_to_string("./tests/fixtures/nws_points_success.json").unwrap();
+            then.status(200)
+                .header("Content-Type", "application/geo+json")
+                .body(json);
+        });
+
+        let mut provider = nws(&server);
+        provider.max_grid_points = 2;
+        let location = |lat| Location::Coordinates { lat, lon: -97.0 };
+        for lat in &[39.0, 40.0, 39.0, 41.0] {
+            provider.grid_point(&location(*lat)).await.unwrap();
+        }
+
+        let mut cached : Vec<String> = provider.grid_points.lock().unwrap().keys().cloned().collect();
+        cached.sort();
+        assert_eq!(cached, vec!["39.0000,-97.0000", "41.0000,-97.0000"]);
+        points.assert_hits_async(3).await;
+    }
+
+    #[actix_rt::test]
+    async fn it_returns_unknown_location_outside_of_us() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/points/48.8500,2.3500");
+            then.status(404)
+                .header("Content-Type", "application/problem+json")
+                .body(r#"{"title": "Data Unavailable For Requested Point", "status": 404}"#);
+        });
+
+        let location = Location::Coordinates { lat: 48.85, lon: 2.35 };
+        let report = nws(&server).get_forecast(&location, 3).await;
+
+        assert!(matches!(report, Err(ProviderError::UnknownLocation)));
+    }
+
+    #[actix_rt::test]
+    async fn it_skips_locations_without_coordinates() {
+        let location = Location::City { name: "topeka".to_string(), country_code: Some("US".to_string()) };
+        let report = NationalWeatherService::new(reqwest::Client::new(), USER_AGENT_VALUE.to_string()).get_forecast(&location, 3).await;
+
+        assert!(matches!(report, Err(ProviderError::UnsupportedLocation(_))));
+    }
+}
@@ -0,0 +1,504 @@
+use crate::{Location, WeatherCondition, WeatherReport};
+use crate::errors::ProviderError;
+use super::{json_from_response, send_with_retry, Capabilities, RetryPolicy, WeatherProvider};
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct OpenMeteo {
+    client: reqwest::Client,
+    api_path_prefix: String,
+    geocoding_path_prefix: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy
+}
+
+#[derive(Deserialize)]
+struct GeocodingResponse {
+    // geocoding answers without `results` field when nothing is found
+    #[serde(default)]
+    results: Vec<Place>
+}
+
+#[derive(Deserialize)]
+struct Place {
+    latitude: f64,
+    longitude: f64
+}
+
+#[derive(Deserialize)]
+struct CurrentResponse {
+    #[serde(default)]
+    utc_offset_seconds: i32,
+    timezone: Option<String>,
+    current: Current
+}
+
+#[derive(Deserialize)]
+struct Current {
+    time: i64,
+    temperature_2m: f64,
+    relative_humidity_2m: Option<f64>,
+    wind_speed_10m: Option<f64>,
+    wind_direction_10m: Option<f64>,
+    pressure_msl: Option<f64>,
+    cloud_cover: Option<f64>,
+    precipitation: Option<f64>,
+    weather_code: Option<u64>
+}
+
+// daily and hourly values come as columns, one array per variable indexed like `time`
+#[derive(Deserialize)]
+struct DailyResponse {
+    #[serde(default)]
+    utc_offset_seconds: i32,
+    timezone: Option<String>,
+    daily: DailyColumns
+}
+
+#[derive(Deserialize)]
+struct DailyColumns {
+    time: Vec<i64>,
+    temperature_2m_min: Vec<f64>,
+    temperature_2m_max: Vec<f64>,
+    #[serde(default)]
+    precipitation_sum: Vec<Option<f64>>,
+    #[serde(default)]
+    weather_code: Vec<Option<u64>>,
+    #[serde(default)]
+    wind_direction_10m_dominant: Vec<Option<f64>>
+}
+
+#[derive(Deserialize)]
+struct HourlyResponse {
+    #[serde(default)]
+    utc_offset_seconds: i32,
+    timezone: Option<String>,
+    hourly: HourlyColumns
+}
+
+#[derive(Deserialize)]
+struct HourlyColumns {
+    time: Vec<i64>,
+    temperature_2m: Vec<f64>,
+    #[serde(default)]
+    relative_humidity_2m: Vec<Option<f64>>,
+    #[serde(default)]
+    wind_speed_10m: Vec<Option<f64>>,
+    #[serde(default)]
+    wind_direction_10m: Vec<Option<f64>>,
+    #[serde(default)]
+    pressure_msl: Vec<Option<f64>>,
+    #[serde(default)]
+    cloud_cover: Vec<Option<f64>>,
+    #[serde(default)]
+    precipitation: Vec<Option<f64>>,
+    #[serde(default)]
+    weather_code: Vec<Option<u64>>
+}
+
+const API_PATH_PREFIX : &str = "https://api.open-meteo.com/v1";
+const GEOCODING_PATH_PREFIX : &str = "https://geocoding-api.open-meteo.com/v1";
+const MAX_FORECAST_DAYS : usize = 16;
+const MAX_FORECAST_HOURS : usize = 16 * 24;
+const CURRENT_VARIABLES : &str = "temperature_2m,relative_humidity_2m,precipitation,weather_code,cloud_cover,pressure_msl,wind_speed_10m,wind_direction_10m";
+const HOURLY_VARIABLES : &str = CURRENT_VARIABLES;
+const DAILY_VARIABLES : &str = "temperature_2m_max,temperature_2m_min,precipitation_sum,weather_code,wind_direction_10m_dominant";
+
+#[async_trait(?Send)]
+impl WeatherProvider for OpenMeteo {
+    fn name(&self) -> &'static str {
+        "open_meteo"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            current: true,
+            forecast: true,
+            max_forecast_days: MAX_FORECAST_DAYS,
+            hourly: true,
+            max_forecast_hours: MAX_FORECAST_HOURS,
+            history_days: 0
+        }
+    }
+
+    async fn get_current(&self, location: &Location) -> Result<WeatherReport, ProviderError> {
+        let mut query = self.location_query(location).await?;
+        query.push(("current", CURRENT_VARIABLES.to_string()));
+        let response = self.get_raw(query).await?;
+        Ok(Self::report_from_current(response))
+    }
+
+    async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let mut query = self.location_query(location).await?;
+        query.push(("daily", DAILY_VARIABLES.to_string()));
+        query.push(("forecast_days", days_count.to_string()));
+        let response = self.get_raw(query).await?;
+        Self::reports_from_daily(response)
+    }
+
+    async fn get_hourly(&self, location: &Location, hours_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let mut query = self.location_query(location).await?;
+        query.push(("hourly", HOURLY_VARIABLES.to_string()));
+        query.push(("forecast_hours", hours_count.to_string()));
+        let response = self.get_raw(query).await?;
+        Self::reports_from_hourly(response)
+    }
+}
+
+impl OpenMeteo {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self::new_with_prefixes(client, API_PATH_PREFIX.to_string(), GEOCODING_PATH_PREFIX.to_string())
+    }
+
+    // forecast and geocoding live on different hosts, a custom prefix (e.g. self-hosted instance or mock) serves both
+    pub fn new_with_prefix(client: reqwest::Client, api_path_prefix: String) -> Self {
+        Self::new_with_prefixes(client, api_path_prefix.clone(), api_path_prefix)
+    }
+
+    fn new_with_prefixes(client: reqwest::Client, api_path_prefix: String, geocoding_path_prefix: String) -> Self {
+        Self { client, api_path_prefix, geocoding_path_prefix, timeout: None, retry: RetryPolicy::default() }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    // forecast api only accepts coordinates, names and postal codes are resolved with geocoding api first
+    async fn location_query(&self, location: &Location) -> Result<Vec<(&'static str, String)>, ProviderError> {
+        let (lat, lon) = match location {
+            Location::Coordinates { lat, lon } => (*lat, *lon),
+            Location::City { name, country_code } => self.geocode(name, country_code.as_deref()).await?,
+            Location::PostalCode { zip, country } => self.geocode(zip, Some(country)).await?,
+            Location::ProviderCityId { .. } =>
+                return Err(ProviderError::UnsupportedLocation(location.clone()))
+        };
+        Ok(vec![("latitude", lat.to_string()), ("longitude", lon.to_string())])
+    }
+
+    async fn geocode(&self, name: &str, country_code: Option<&str>) -> Result<(f64, f64), ProviderError> {
+        let full_path = format!("{}/search", self.geocoding_path_prefix);
+        let mut query = vec![("name", name.to_string()), ("count", String::from("1")), ("format", String::from("json"))];
+        if let Some(country_code) = country_code {
+            query.push(("countryCode", country_code.to_uppercase()));
+        }
+        let response : GeocodingResponse = self.get_json(full_path, query).await?;
+        response.results
+            .first()
+            .map(|place| (place.latitude, place.longitude))
+            .ok_or(ProviderError::UnknownLocation)
+    }
+
+    async fn get_raw<T: DeserializeOwned>(&self, mut query: Vec<(&'static str, String)>) -> Result<T, ProviderError> {
+        let full_path = format!("{}/forecast", self.api_path_prefix);
+        query.push(("timezone", String::from("auto")));
+        query.push(("timeformat", String::from("unixtime")));
+        query.push(("wind_speed_unit", String::from("ms")));
+        self.get_json(full_path, query).await
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, full_path: String, query: Vec<(&'static str, String)>) -> Result<T, ProviderError> {
+        let mut request = self.client
+            .get(&full_path)
+            .query(&query)
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let response = send_with_retry(request, &self.retry).await?;
+
+        json_from_response(response).await
+    }
+
+    fn report_from_current(response: CurrentResponse) -> WeatherReport {
+        let current = response.current;
+        WeatherReport {
+            temperature: current.temperature_2m,
+            unix_timestamp: current.time,
+            utc_offset: response.utc_offset_seconds,
+            timezone: response.timezone,
+            humidity: current.relative_humidity_2m,
+            wind_speed: current.wind_speed_10m,
+            wind_direction: current.wind_direction_10m,
+            pressure: current.pressure_msl,
+            cloud_cover: current.cloud_cover,
+            precipitation: current.precipitation,
+            condition: current.weather_code.and_then(Self::parse_condition),
+            ..Default::default()
+        }
+    }
+
+    fn reports_from_daily(response: DailyResponse) -> Result<Vec<WeatherReport>, ProviderError> {
+        let daily = &response.daily;
+        daily.time.iter()
+            .enumerate()
+            .map(|(index, &timestamp)| {
+                let low = Self::required(&daily.temperature_2m_min, index, "/daily/temperature_2m_min")?;
+                let high = Self::required(&daily.temperature_2m_max, index, "/daily/temperature_2m_max")?;
+                Ok(WeatherReport {
+                    temperature: (low + high) / 2.0,
+                    unix_timestamp: timestamp,
+                    utc_offset: response.utc_offset_seconds,
+                    timezone: response.timezone.clone(),
+                    temperature_min: Some(low),
+                    temperature_max: Some(high),
+                    wind_direction: Self::optional(&daily.wind_direction_10m_dominant, index),
+                    precipitation: Self::optional(&daily.precipitation_sum, index),
+                    condition: Self::optional(&daily.weather_code, index).and_then(Self::parse_condition),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn reports_from_hourly(response: HourlyResponse) -> Result<Vec<WeatherReport>, ProviderError> {
+        let hourly = &response.hourly;
+        hourly.time.iter()
+            .enumerate()
+            .map(|(index, &timestamp)| {
+                Ok(WeatherReport {
+                    temperature: Self::required(&hourly.temperature_2m, index, "/hourly/temperature_2m")?,
+                    unix_timestamp: timestamp,
+                    utc_offset: response.utc_offset_seconds,
+                    timezone: response.timezone.clone(),
+                    humidity: Self::optional(&hourly.relative_humidity_2m, index),
+                    wind_speed: Self::optional(&hourly.wind_speed_10m, index),
+                    wind_direction: Self::optional(&hourly.wind_direction_10m, index),
+                    pressure: Self::optional(&hourly.pressure_msl, index),
+                    cloud_cover: Self::optional(&hourly.cloud_cover, index),
+                    precipitation: Self::optional(&hourly.precipitation, index),
+                    condition: Self::optional(&hourly.weather_code, index).and_then(Self::parse_condition),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    // a column shorter than `time` is as broken as a missing field
+    fn required(column: &[f64], index: usize, pointer: &str) -> Result<f64, ProviderError> {
+        column.get(index)
+            .copied()
+            .ok_or_else(|| ProviderError::Parse(format!("invalid response: missing value at {}/{}", pointer, index)))
+    }
+
+    fn optional<T: Copy>(column: &[Option<T>], index: usize) -> Option<T> {
+        column.get(index).copied().flatten()
+    }
+
+    // WMO weather codes are translated to openweather ones, so conditions of all providers can be compared
+    fn parse_condition(code: u64) -> Option<WeatherCondition> {
+        let (code, description) = match code {
+            0 => (800, "clear sky"),
+            1 => (801, "few clouds"),
+            2 => (802, "scattered clouds"),
+            3 => (804, "overcast clouds"),
+            45 | 48 => (741, "fog"),
+            51 => (300, "light intensity drizzle"),
+            53 => (301, "drizzle"),
+            55 => (302, "heavy intensity drizzle"),
+            56 | 57 | 66 | 67 => (511, "freezing rain"),
+            61 => (500, "light rain"),
+            63 => (501, "moderate rain"),
+            65 => (502, "heavy intensity rain"),
+            71 | 77 => (600, "light snow"),
+            73 => (601, "snow"),
+            75 => (602, "heavy snow"),
+            80 => (520, "light intensity shower rain"),
+            81 => (521, "shower rain"),
+            82 => (522, "heavy intensity shower rain"),
+            85 => (620, "light shower snow"),
+            86 => (622, "heavy shower snow"),
+            95 | 96 | 99 => (211, "thunderstorm"),
+            _ => return None
+        };
+        Some(WeatherCondition { code, description: description.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weather_aggregator::weather_clients::parse_json;
+    use httpmock::MockServer;
+    use httpmock::Method::GET;
+
+    fn kazan() -> Location {
+        Location::City { name: "kazan".to_string(), country_code: Some("ru".to_string()) }
+    }
+
+    fn mock_geocoding(server: &MockServer) -> httpmock::MockRef<'_> {
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/search")
+                .query_param("name", "kazan")
+                .query_param("countryCode", "RU");
+
+            let json = std::fs::read_to_string("./tests/fixtures/open_meteo_geocoding_success.json").unwrap();
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(json);
+        })
+    }
+
+    #[test]
+    fn it_fails_to_deserialize_current_weather_invalid_raw_json() {
+        let raw_json = r#"{"current": {"time": 1614078000, "temperature": -17.2}}"#;
+
+        let error = parse_json(raw_json).map(OpenMeteo::report_from_current).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "invalid response: missing field `temperature_2m` at /current near `{\"temperature\":-17.2,\"time\":1614078000}`"
+        );
+    }
+
+    #[test]
+    fn it_fails_on_hourly_column_shorter_than_time() {
+        let raw_json = r#"{"hourly": {"time": [1614078000, 1614081600], "temperature_2m": [-17.2]}}"#;
+
+        let error = parse_json(raw_json).and_then(OpenMeteo::reports_from_hourly).unwrap_err();
+
+        assert_eq!(error.to_string(), "invalid response: missing value at /hourly/temperature_2m/1");
+    }
+
+    #[test]
+    fn it_deserializes_hourly_columns() {
+        let raw_json = r#"
+        {
+            "utc_offset_seconds": 10800,
+            "timezone": "Europe/Moscow",
+            "hourly": {
+                "time": [1614078000, 1614081600],
+                "temperature_2m": [-17.2, -18.0],
+                "relative_humidity_2m": [73, null],
+                "weather_code": [3, 71]
+            }
+        }
+        "#;
+        let hours = parse_json(raw_json).and_then(OpenMeteo::reports_from_hourly).unwrap();
+
+        assert_eq!(hours.len(), 2);
+        assert_eq!(hours[1].temperature, -18.0);
+        assert_eq!(hours[0].humidity, Some(73.0));
+        assert_eq!(hours[1].humidity, None);
+        assert_eq!(hours[1].utc_offset, 10800);
+        assert_eq!(hours[1].condition, Some(WeatherCondition { code: 600, description: "light snow".to_string() }));
+    }
+
+    #[actix_rt::test]
+    async fn it_fetches_current_weather_for_geocoded_city() {
+        let server = MockServer::start();
+        let geocoding = mock_geocoding(&server);
+        let forecast = server.mock(|when, then| {
+            when.method(GET)
+                .path("/forecast")
+                .query_param("latitude", "55.78874")
+                .query_param("longitude", "49.12214")
+                .query_param("timezone", "auto")
+                .query_param("wind_speed_unit", "ms");
+
+            let json = std::fs::read_to_string("./tests/fixtures/open_meteo_current_success.json").unwrap();
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(json);
+        });
+
+        let report = OpenMeteo::new_with_prefix(reqwest::Client::new(), server.url("")).get_current(&kazan()).await;
+
+        let report = report.unwrap();
+        assert_eq!(report.temperature, -17.2);
+        assert_eq!(report.unix_timestamp, 1614078000);
+        assert_eq!(report.utc_offset, 10800);
+        assert_eq!(report.timezone.as_deref(), Some("Europe/Moscow"));
+        assert_eq!(report.humidity, Some(73.0));
+        assert_eq!(report.wind_speed, Some(4.8));
+        assert_eq!(report.pressure, Some(1021.4));
+        assert_eq!(report.condition, Some(WeatherCondition { code: 802, description: "scattered clouds".to_string() }));
+        geocoding.assert_async().await;
+        forecast.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn it_fetches_daily_forecast() {
+        let server = MockServer::start();
+        mock_geocoding(&server);
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/forecast")
+                .query_param("forecast_days", "2");
+
+            let json = std::fs::read_to_string("./tests/fixtures/open_meteo_daily_success.json").unwrap();
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(json);
+        });
+
+        let forecast = OpenMeteo::new_with_prefix(reqwest::Client::new(), server.url(""))
+            .get_forecast(&kazan(), 2)
+            .await
+            .unwrap();
+
+        assert_eq!(forecast.len(), 2);
+        assert_eq!(forecast[0].temperature, -19.0);
+        assert_eq!(forecast[0].temperature_min, Some(-23.5));
+        assert_eq!(forecast[0].temperature_max, Some(-14.5));
+        assert_eq!(forecast[0].precipitation, Some(0.3));
+        assert_eq!(forecast[1].local_date(), chrono::NaiveDate::from_ymd(2021, 2, 24));
+        mock.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn it_queries_coordinates_without_geocoding() {
+        let server = MockServer::start();
+        let geocoding = mock_geocoding(&server);
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/forecast")
+                .query_param("latitude", "48.85")
+                .query_param("longitude", "2.35");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{"utc_offset_seconds": 3600, "current": {"time": 1614078000, "temperature_2m": 7.5}}"#);
+        });
+
+        let location = Location::Coordinates { lat: 48.85, lon: 2.35 };
+        let report = OpenMeteo::new_with_prefix(reqwest::Client::new(), server.url("")).get_current(&location).await;
+
+        assert_eq!(report.unwrap().temperature, 7.5);
+        geocoding.assert_hits_async(0).await;
+    }
+
+    #[actix_rt::test]
+    async fn it_returns_unknown_location_when_geocoding_finds_nothing() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/search");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{"generationtime_ms": 0.52}"#);
+        });
+
+        let location = Location::City { name: "nowhereville".to_string(), country_code: None };
+        let report = OpenMeteo::new_with_prefix(reqwest::Client::new(), server.url("")).get_current(&location).await;
+
+        assert!(matches!(report, Err(ProviderError::UnknownLocation)));
+    }
+
+    #[actix_rt::test]
+    async fn it_skips_other_providers_city_ids() {
+        let location = Location::ProviderCityId { provider: "open_weather".to_string(), id: "551487".to_string() };
+        let report = OpenMeteo::new(reqwest::Client::new()).get_current(&location).await;
+
+        assert!(matches!(report, Err(ProviderError::UnsupportedLocation(_))));
+    }
+}
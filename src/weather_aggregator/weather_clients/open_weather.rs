@@ -1,119 +1,388 @@
-use crate::WeatherReport;
+use crate::{Location, WeatherCondition, WeatherReport};
+use crate::errors::ProviderError;
+use super::{daily_from_hourly, json_from_response, send_with_retry, Capabilities, RetryPolicy, WeatherProvider};
+use chrono::NaiveDate;
+use async_trait::async_trait;
 use reqwest::header::CONTENT_TYPE;
-use std::error::Error;
-use std::fmt;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct OpenWeather {
+    client: reqwest::Client,
     api_key: String,
-    api_path_prefix: String
+    api_path_prefix: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy
 }
 
-#[derive(Debug)]
-struct OpenWeatherJsonParseError;
-impl Error for OpenWeatherJsonParseError {}
-impl fmt::Display for OpenWeatherJsonParseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Failed to build weather report from open weather response json")
-    }
+#[derive(Deserialize)]
+struct CurrentResponse {
+    dt: i64,
+    // current weather reports utc offset in seconds as `timezone`
+    #[serde(default)]
+    timezone: i32,
+    coord: Option<Coordinates>,
+    main: CurrentMain,
+    #[serde(default)]
+    wind: Wind,
+    #[serde(default)]
+    clouds: Clouds,
+    #[serde(default)]
+    rain: HourlyVolume,
+    #[serde(default)]
+    snow: HourlyVolume,
+    #[serde(default)]
+    weather: Vec<Condition>
+}
+
+#[derive(Deserialize)]
+struct Coordinates {
+    lat: f64,
+    lon: f64
+}
+
+#[derive(Deserialize)]
+struct CurrentMain {
+    temp: f64,
+    humidity: Option<f64>,
+    pressure: Option<f64>
+}
+
+#[derive(Deserialize, Default)]
+struct Wind {
+    speed: Option<f64>,
+    deg: Option<f64>
+}
+
+#[derive(Deserialize, Default)]
+struct Clouds {
+    all: Option<f64>
+}
+
+#[derive(Deserialize, Default)]
+struct HourlyVolume {
+    #[serde(rename = "1h")]
+    last_hour: Option<f64>
+}
+
+#[derive(Deserialize)]
+struct Condition {
+    id: u32,
+    #[serde(default)]
+    description: String
+}
+
+#[derive(Deserialize)]
+struct DailyResponse {
+    #[serde(default)]
+    timezone_offset: i32,
+    timezone: Option<String>,
+    daily: Vec<Day>
+}
+
+#[derive(Deserialize)]
+struct Day {
+    dt: i64,
+    temp: DayTemperature,
+    humidity: Option<f64>,
+    wind_speed: Option<f64>,
+    wind_deg: Option<f64>,
+    pressure: Option<f64>,
+    clouds: Option<f64>,
+    rain: Option<f64>,
+    snow: Option<f64>,
+    #[serde(default)]
+    weather: Vec<Condition>
+}
+
+#[derive(Deserialize)]
+struct DayTemperature {
+    day: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+    morn: Option<f64>,
+    eve: Option<f64>,
+    night: Option<f64>
+}
+
+#[derive(Deserialize)]
+struct HourlyResponse {
+    #[serde(default)]
+    timezone_offset: i32,
+    timezone: Option<String>,
+    hourly: Vec<Hour>
+}
+
+#[derive(Deserialize)]
+struct Hour {
+    dt: i64,
+    temp: f64,
+    humidity: Option<f64>,
+    wind_speed: Option<f64>,
+    wind_deg: Option<f64>,
+    pressure: Option<f64>,
+    clouds: Option<f64>,
+    #[serde(default)]
+    rain: HourlyVolume,
+    #[serde(default)]
+    snow: HourlyVolume,
+    #[serde(default)]
+    weather: Vec<Condition>
 }
 
 const API_PATH_PREFIX : &str = "http://api.openweathermap.org/data/2.5";
-impl OpenWeather {
-    pub fn new(api_key: String) -> Self {
-        Self { api_key, api_path_prefix: API_PATH_PREFIX.to_string() }
+const MAX_FORECAST_DAYS : usize = 8;
+const MAX_FORECAST_HOURS : usize = 48;
+// timemachine only keeps last five days
+const MAX_HISTORY_DAYS : usize = 5;
+
+#[async_trait(?Send)]
+impl WeatherProvider for OpenWeather {
+    fn name(&self) -> &'static str {
+        "open_weather"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            current: true,
+            forecast: true,
+            max_forecast_days: MAX_FORECAST_DAYS,
+            hourly: true,
+            max_forecast_hours: MAX_FORECAST_HOURS,
+            history_days: MAX_HISTORY_DAYS
+        }
     }
 
-    #[cfg(test)]
-    pub fn new_with_prefix(api_key: String, api_path_prefix: String) -> Self {
-        Self { api_key, api_path_prefix }
+    async fn get_current(&self, location: &Location) -> Result<WeatherReport, ProviderError> {
+        let current = self.get_current_response(location).await?;
+        Ok(Self::report_from_current(current))
     }
 
-    pub async fn get_current(&self, city_name: &str) -> Result<WeatherReport, Box<dyn Error>> {
-        let raw_json = self.get_raw_current(city_name).await?;
-        Self::parse_report_from_raw_json(raw_json)
+    async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let (lat, lon) = self.coordinates(location).await?;
+        let mut weekly_forecast = self.get_onecall_forecast(lat, lon).await?;
+        weekly_forecast.truncate(days_count);
+        Ok(weekly_forecast)
     }
 
-    pub async fn get_forecast(&self, city_name: &str, days_count: usize) -> Result<Vec<WeatherReport>, Box<dyn Error>> {
-        let current_json = self.get_raw_current(city_name).await?;
-        let lat = current_json["coord"]["lat"].as_f64();
-        let lon = current_json["coord"]["lon"].as_f64();
-        if let (Some(lat), Some(lon)) = (lat, lon) {
-            let mut weekly_forecast = self.get_onecall_forecast(lat, lon).await?;
-            weekly_forecast.truncate(days_count);
-            Ok(weekly_forecast)
-        } else {
-            Err(OpenWeatherJsonParseError.into())
+    async fn get_hourly(&self, location: &Location, hours_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let (lat, lon) = self.coordinates(location).await?;
+        let mut hourly_forecast = self.get_onecall_hourly(lat, lon).await?;
+        hourly_forecast.truncate(hours_count);
+        Ok(hourly_forecast)
+    }
+
+    // timemachine returns hours of the UTC day containing dt, so the local day of a location
+    // away from UTC takes hours of the neighbouring UTC day as well
+    async fn get_history(&self, location: &Location, date: NaiveDate) -> Result<WeatherReport, ProviderError> {
+        let (lat, lon) = self.coordinates(location).await?;
+        let response = self.get_timemachine(lat, lon, date).await?;
+        let local_day_start = date.and_hms(0, 0, 0) - chrono::Duration::seconds(response.timezone_offset as i64);
+        let local_day_end = local_day_start + chrono::Duration::hours(23);
+        let neighbour_date = [local_day_start.date(), local_day_end.date()]
+            .iter()
+            .copied()
+            .find(|utc_date| *utc_date != date);
+
+        let mut hours = Self::reports_from_hourly(response);
+        if let Some(neighbour_date) = neighbour_date {
+            hours.extend(Self::reports_from_hourly(self.get_timemachine(lat, lon, neighbour_date).await?));
         }
+        hours.retain(|hour| hour.local_date() == date);
+        hours.sort_by_key(|hour| hour.unix_timestamp);
+        hours.dedup_by_key(|hour| hour.unix_timestamp);
+        daily_from_hourly(hours).ok_or_else(|| ProviderError::Parse(format!("timemachine response has no hours for {}", date)))
     }
+}
 
-    pub async fn get_raw_current(&self, city_name: &str) -> Result<serde_json::Value, Box<dyn Error>> {
-        let full_path = format!("{}/weather?APPID={}&q={}&units=metric",
-                                self.api_path_prefix, self.api_key, city_name);
-        Ok(Self::get_raw(full_path).await?)
+impl OpenWeather {
+    pub fn new(client: reqwest::Client, api_key: String) -> Self {
+        Self { client, api_key, api_path_prefix: API_PATH_PREFIX.to_string(), timeout: None, retry: RetryPolicy::default() }
     }
 
-    pub async fn get_onecall_forecast(&self, lat: f64, lon: f64) -> Result<Vec<WeatherReport>, Box<dyn Error>> {
-        let full_path = format!("{}/onecall?APPID={}&lat={}&lon={}&units=metric&&exclude=current,minutely,hourly",
-                                self.api_path_prefix, self.api_key, lat, lon);
-        let raw_json = Self::get_raw(full_path).await?;
-        Self::parse_report_array_from_raw_json(raw_json)
+    pub fn new_with_prefix(client: reqwest::Client, api_key: String, api_path_prefix: String) -> Self {
+        Self { client, api_key, api_path_prefix, timeout: None, retry: RetryPolicy::default() }
     }
 
-    async fn get_raw(full_path: String) -> Result<serde_json::Value, Box<dyn Error>> {
-        let client = reqwest::Client::new();
-        let raw_result = client
-            .get(&full_path)
-            .header(CONTENT_TYPE, "application/json")
-            .send()
-            .await?
-            .json()
-            .await?;
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
 
-        Ok(raw_result)
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
     }
 
-    fn parse_report_from_raw_json(data: serde_json::Value) -> Result<WeatherReport, Box<dyn Error>> {
-        Self::parse_report_from_open_weather_current_json_struct(&data)
+    async fn get_current_response(&self, location: &Location) -> Result<CurrentResponse, ProviderError> {
+        let full_path = format!("{}/weather", self.api_path_prefix);
+        let mut query = self.location_query(location)?;
+        query.push(("units", "metric".to_string()));
+        self.get_raw(full_path, query).await
     }
 
-    fn parse_report_array_from_raw_json(data: serde_json::Value) -> Result<Vec<WeatherReport>, Box<dyn Error>> {
-        let array = data["daily"].as_array();
-        if let Some(array) = array {
-            array.iter().map(Self::parse_report_from_open_weather_onecall_json_struct).collect()
-        } else {
-            Err(OpenWeatherJsonParseError.into())
+    // onecall only accepts coordinates, other locations are resolved via current weather
+    async fn coordinates(&self, location: &Location) -> Result<(f64, f64), ProviderError> {
+        if let Location::Coordinates { lat, lon } = location {
+            return Ok((*lat, *lon));
         }
+
+        let current = self.get_current_response(location).await?;
+        current.coord
+            .map(|coord| (coord.lat, coord.lon))
+            .ok_or_else(|| ProviderError::Parse(String::from("invalid response: missing field `coord` at /")))
+    }
+
+    async fn get_onecall_hourly(&self, lat: f64, lon: f64) -> Result<Vec<WeatherReport>, ProviderError> {
+        let full_path = format!("{}/onecall", self.api_path_prefix);
+        let query = vec![
+            ("lat", lat.to_string()),
+            ("lon", lon.to_string()),
+            ("units", "metric".to_string()),
+            ("exclude", "current,minutely,daily".to_string())
+        ];
+        let response = self.get_raw(full_path, query).await?;
+        Ok(Self::reports_from_hourly(response))
+    }
+
+    async fn get_onecall_forecast(&self, lat: f64, lon: f64) -> Result<Vec<WeatherReport>, ProviderError> {
+        let full_path = format!("{}/onecall", self.api_path_prefix);
+        let query = vec![
+            ("lat", lat.to_string()),
+            ("lon", lon.to_string()),
+            ("units", "metric".to_string()),
+            ("exclude", "current,minutely,hourly".to_string())
+        ];
+        let response = self.get_raw(full_path, query).await?;
+        Ok(Self::reports_from_daily(response))
+    }
+
+    fn location_query(&self, location: &Location) -> Result<Vec<(&'static str, String)>, ProviderError> {
+        let query = match location {
+            Location::Coordinates { lat, lon } => vec![("lat", lat.to_string()), ("lon", lon.to_string())],
+            Location::PostalCode { zip, country } => vec![("zip", format!("{},{}", zip, country))],
+            Location::City { name, country_code: Some(country_code) } => vec![("q", format!("{},{}", name, country_code))],
+            Location::City { name, country_code: None } => vec![("q", name.clone())],
+            Location::ProviderCityId { provider, id } if provider == self.name() => vec![("id", id.clone())],
+            Location::ProviderCityId { .. } =>
+                return Err(ProviderError::UnsupportedLocation(location.clone()))
+        };
+        Ok(query)
     }
 
-    fn parse_report_from_open_weather_onecall_json_struct(data: &serde_json::Value) -> Result<WeatherReport, Box<dyn Error>> {
-        let temp = data["temp"]["day"].as_f64();
-        let timestamp = data["dt"].as_i64();
-        if let (Some(temperature), Some(timestamp)) = (temp, timestamp) {
-            Ok(WeatherReport { temperature, unix_timestamp: timestamp })
-        } else {
-            Err(OpenWeatherJsonParseError.into())
+    // dt may not be in the future, which noon of today's UTC date could be
+    async fn get_timemachine(&self, lat: f64, lon: f64, utc_date: NaiveDate) -> Result<HourlyResponse, ProviderError> {
+        let full_path = format!("{}/onecall/timemachine", self.api_path_prefix);
+        let dt = utc_date.and_hms(12, 0, 0).timestamp().min(chrono::Utc::now().timestamp());
+        let query = vec![
+            ("lat", lat.to_string()),
+            ("lon", lon.to_string()),
+            ("dt", dt.to_string()),
+            ("units", "metric".to_string())
+        ];
+        self.get_raw(full_path, query).await
+    }
+
+    async fn get_raw<T: DeserializeOwned>(&self, full_path: String, mut query: Vec<(&'static str, String)>) -> Result<T, ProviderError> {
+        query.push(("APPID", self.api_key.clone()));
+        let mut request = self.client
+            .get(&full_path)
+            .query(&query)
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
         }
+        let response = send_with_retry(request, &self.retry).await?;
+
+        json_from_response(response).await
     }
 
-    fn parse_report_from_open_weather_current_json_struct(data: &serde_json::Value) -> Result<WeatherReport, Box<dyn Error>> {
-        let temp = data["main"]["temp"].as_f64();
-        let timestamp = data["dt"].as_i64();
-        if let (Some(temperature), Some(timestamp)) = (temp, timestamp) {
-            Ok(WeatherReport { temperature, unix_timestamp: timestamp })
-        } else {
-            Err(OpenWeatherJsonParseError.into())
+    fn report_from_current(current: CurrentResponse) -> WeatherReport {
+        WeatherReport {
+            temperature: current.main.temp,
+            unix_timestamp: current.dt,
+            utc_offset: current.timezone,
+            humidity: current.main.humidity,
+            wind_speed: current.wind.speed,
+            wind_direction: current.wind.deg,
+            pressure: current.main.pressure,
+            cloud_cover: current.clouds.all,
+            precipitation: Some(current.rain.last_hour.unwrap_or(0.0) + current.snow.last_hour.unwrap_or(0.0)),
+            condition: Self::condition(current.weather),
+            ..Default::default()
         }
     }
-}
 
+    fn reports_from_daily(response: DailyResponse) -> Vec<WeatherReport> {
+        let utc_offset = response.timezone_offset;
+        let timezone = response.timezone;
+        response.daily
+            .into_iter()
+            .map(|day| WeatherReport {
+                temperature: day.temp.day,
+                unix_timestamp: day.dt,
+                utc_offset,
+                timezone: timezone.clone(),
+                temperature_min: day.temp.min,
+                temperature_max: day.temp.max,
+                temperature_morning: day.temp.morn,
+                temperature_evening: day.temp.eve,
+                temperature_night: day.temp.night,
+                humidity: day.humidity,
+                wind_speed: day.wind_speed,
+                wind_direction: day.wind_deg,
+                pressure: day.pressure,
+                cloud_cover: day.clouds,
+                precipitation: Some(day.rain.unwrap_or(0.0) + day.snow.unwrap_or(0.0)),
+                condition: Self::condition(day.weather)
+            })
+            .collect()
+    }
+
+    fn reports_from_hourly(response: HourlyResponse) -> Vec<WeatherReport> {
+        let utc_offset = response.timezone_offset;
+        let timezone = response.timezone;
+        response.hourly
+            .into_iter()
+            .map(|hour| WeatherReport {
+                temperature: hour.temp,
+                unix_timestamp: hour.dt,
+                utc_offset,
+                timezone: timezone.clone(),
+                humidity: hour.humidity,
+                wind_speed: hour.wind_speed,
+                wind_direction: hour.wind_deg,
+                pressure: hour.pressure,
+                cloud_cover: hour.clouds,
+                precipitation: Some(hour.rain.last_hour.unwrap_or(0.0) + hour.snow.last_hour.unwrap_or(0.0)),
+                condition: Self::condition(hour.weather),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn condition(weather: Vec<Condition>) -> Option<WeatherCondition> {
+        weather
+            .into_iter()
+            .next()
+            .map(|condition| WeatherCondition { code: condition.id, description: condition.description })
+    }
+}
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use crate::weather_aggregator::weather_clients::parse_json;
     use httpmock::MockServer;
     use httpmock::Method::GET;
 
+    fn kazan() -> Location {
+        Location::City { name: "kazan".to_string(), country_code: None }
+    }
+
     #[test]
     fn it_deserializes_current_weather_valid_raw_json() {
         let raw_json = r#"
@@ -125,9 +394,8 @@ mod tests {
             "name": "Moscow"
         }
         "#;
-        let json_value = serde_json::from_str(raw_json).unwrap();
 
-        let weather_report = OpenWeather::parse_report_from_raw_json(json_value).unwrap();
+        let weather_report = parse_json(raw_json).map(OpenWeather::report_from_current).unwrap();
         assert_eq!(weather_report.temperature, -12.94);
         assert_eq!(weather_report.unix_timestamp, 1613978904);
     }
@@ -143,12 +411,10 @@ mod tests {
             "name": "Moscow"
         }
         "#;
-        let json_value = serde_json::from_str(raw_json).unwrap();
 
-        assert_eq!(
-            OpenWeather::parse_report_from_raw_json(json_value).is_err(),
-            true
-        )
+        let error = parse_json(raw_json).map(OpenWeather::report_from_current).unwrap_err();
+
+        assert_eq!(error.to_string(), "invalid response: missing field `temp` at /main near `{\"xtemp\":-12.94}`");
     }
 
 
@@ -172,9 +438,8 @@ mod tests {
             ]
         }
         "#;
-        let json_value = serde_json::from_str(raw_json).unwrap();
 
-        let parsed_reports = OpenWeather::parse_report_array_from_raw_json(json_value).unwrap();
+        let parsed_reports = parse_json(raw_json).map(OpenWeather::reports_from_daily).unwrap();
 
         assert_eq!(parsed_reports[0].temperature, -13.45);
         assert_eq!(parsed_reports[0].unix_timestamp, 1613984400);
@@ -182,6 +447,73 @@ mod tests {
         assert_eq!(parsed_reports[1].unix_timestamp, 1613995200);
     }
 
+    #[test]
+    fn it_deserializes_forecast_weather_details() {
+        let raw_json = r#"
+        {
+            "daily": [
+                {
+                    "dt": 1613984400,
+                    "temp": {
+                        "day": -13.45,
+                        "min": -17.2,
+                        "max": -12.9,
+                        "night": -16.8,
+                        "eve": -14.1,
+                        "morn": -17.0
+                    },
+                    "pressure": 1031,
+                    "humidity": 84,
+                    "wind_speed": 3.2,
+                    "wind_deg": 190,
+                    "clouds": 100,
+                    "snow": 0.45,
+                    "weather": [{"id": 600, "main": "Snow", "description": "light snow"}]
+                }
+            ]
+        }
+        "#;
+
+        let report = parse_json(raw_json).map(OpenWeather::reports_from_daily).unwrap().remove(0);
+
+        assert_eq!(report.temperature_min, Some(-17.2));
+        assert_eq!(report.temperature_max, Some(-12.9));
+        assert_eq!(report.temperature_morning, Some(-17.0));
+        assert_eq!(report.temperature_evening, Some(-14.1));
+        assert_eq!(report.temperature_night, Some(-16.8));
+        assert_eq!(report.humidity, Some(84.0));
+        assert_eq!(report.wind_speed, Some(3.2));
+        assert_eq!(report.wind_direction, Some(190.0));
+        assert_eq!(report.pressure, Some(1031.0));
+        assert_eq!(report.cloud_cover, Some(100.0));
+        assert_eq!(report.precipitation, Some(0.45));
+        assert_eq!(report.condition, Some(WeatherCondition { code: 600, description: "light snow".to_string() }));
+    }
+
+    #[test]
+    fn it_applies_timezone_offset_to_forecast_reports() {
+        let raw_json = r#"
+        {
+            "timezone": "Asia/Tokyo",
+            "timezone_offset": 32400,
+            "daily": [
+                {
+                    "temp": {
+                        "day": 8.5
+                    },
+                    "dt": 1613930400
+                }
+            ]
+        }
+        "#;
+
+        let parsed_reports = parse_json(raw_json).map(OpenWeather::reports_from_daily).unwrap();
+
+        assert_eq!(parsed_reports[0].utc_offset, 32400);
+        assert_eq!(parsed_reports[0].timezone.as_deref(), Some("Asia/Tokyo"));
+        assert_eq!(parsed_reports[0].local_date(), chrono::NaiveDate::from_ymd(2021, 2, 22));
+    }
+
     #[test]
     fn it_fails_to_deserialize_forecast_weather_invalid_raw_json() {
         let raw_json = r#"
@@ -202,14 +534,43 @@ mod tests {
             ]
         }
         "#;
-        let json_value = serde_json::from_str(raw_json).unwrap();
 
         assert_eq!(
-            OpenWeather::parse_report_array_from_raw_json(json_value).is_err(),
+            parse_json(raw_json).map(OpenWeather::reports_from_daily).is_err(),
             true
         )
     }
 
+    #[test]
+    fn it_deserializes_hourly_forecast_raw_json() {
+        let raw_json = r#"
+        {
+            "timezone_offset": 10800,
+            "hourly": [
+                {
+                    "dt": 1614078000,
+                    "temp": -17.5,
+                    "humidity": 80,
+                    "wind_deg": 250,
+                    "snow": { "1h": 0.3 },
+                    "weather": [{ "id": 600, "description": "light snow" }]
+                },
+                { "dt": 1614081600, "temp": -18.1 }
+            ]
+        }
+        "#;
+
+        let parsed_reports = parse_json(raw_json).map(OpenWeather::reports_from_hourly).unwrap();
+
+        assert_eq!(parsed_reports.len(), 2);
+        assert_eq!(parsed_reports[0].temperature, -17.5);
+        assert_eq!(parsed_reports[0].utc_offset, 10800);
+        assert_eq!(parsed_reports[0].humidity, Some(80.0));
+        assert_eq!(parsed_reports[0].precipitation, Some(0.3));
+        assert_eq!(parsed_reports[0].condition, Some(WeatherCondition { code: 600, description: "light snow".to_string() }));
+        assert_eq!(parsed_reports[1].temperature, -18.1);
+    }
+
     #[actix_rt::test]
     async fn it_fetches_data_from_open_weather_service() {
         let server = MockServer::start();
@@ -224,10 +585,139 @@ mod tests {
         });
 
         let key = "apikey".to_string();
-        let report = OpenWeather::new_with_prefix(key, server.url("")).get_current("kazan").await;
+        let report = OpenWeather::new_with_prefix(reqwest::Client::new(), key, server.url("")).get_current(&kazan()).await;
+
+        let report = report.unwrap();
+        assert_eq!(report.temperature, -26.0);
+        assert_eq!(report.humidity, Some(70.0));
+        assert_eq!(report.wind_speed, Some(8.0));
+        assert_eq!(report.wind_direction, Some(360.0));
+        assert_eq!(report.pressure, Some(1025.0));
+        assert_eq!(report.cloud_cover, Some(0.0));
+        assert_eq!(report.precipitation, Some(0.0));
+        assert_eq!(report.condition, Some(WeatherCondition { code: 800, description: "clear sky".to_string() }));
+    }
+
+    #[actix_rt::test]
+    async fn it_translates_location_into_query_params() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/weather")
+                .query_param("q", "Paris,US")
+                .query_param("APPID", "apikey");
+
+            let json = std::fs::read_to_string("./tests/fixtures/open_weather_current_success.json").unwrap();
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(json);
+        });
+
+        let location = Location::City { name: "Paris".to_string(), country_code: Some("US".to_string()) };
+        let report = OpenWeather::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), server.url("")).get_current(&location).await;
 
         assert_eq!(report.is_ok(), true);
-        assert_eq!(report.unwrap().temperature, -26.0);
+        mock.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn it_fetches_forecast_by_coordinates_without_current_lookup() {
+        let server = MockServer::start();
+        let current_mock = server.mock(|when, then| {
+            when.method(GET).path("/weather");
+            then.status(500);
+        });
+        let onecall_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/onecall")
+                .query_param("lat", "55.78")
+                .query_param("lon", "49.12");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{"timezone_offset": 10800, "daily": [{"dt": 1614070800, "temp": {"day": -20.5}}]}"#);
+        });
+
+        let location = Location::Coordinates { lat: 55.78, lon: 49.12 };
+        let forecast = OpenWeather::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), server.url("")).get_forecast(&location, 5).await;
+
+        assert_eq!(forecast.unwrap()[0].temperature, -20.5);
+        onecall_mock.assert_async().await;
+        current_mock.assert_hits_async(0).await;
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_city_id_of_another_provider() {
+        let location = Location::ProviderCityId { provider: "weatherbit".to_string(), id: "551487".to_string() };
+        let report = OpenWeather::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), "http://localhost:1".to_string()).get_current(&location).await;
+
+        assert_eq!(report.is_err(), true);
+    }
+
+    #[actix_rt::test]
+    async fn it_folds_timemachine_hours_into_daily_history() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/onecall/timemachine")
+                .query_param("lat", "55.79")
+                .query_param("lon", "49.12")
+                .query_param("dt", "1613822400");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{
+                    "timezone_offset": 10800,
+                    "hourly": [
+                        { "dt": 1613779200, "temp": -22.0 },
+                        { "dt": 1613800800, "temp": -18.0 },
+                        { "dt": 1613854800, "temp": -30.0 }
+                    ]
+                }"#);
+        });
+        // local day starts at 21:00 UTC of the previous day
+        let previous_day_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/onecall/timemachine")
+                .query_param("dt", "1613736000");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{
+                    "timezone_offset": 10800,
+                    "hourly": [
+                        { "dt": 1613764800, "temp": -35.0 },
+                        { "dt": 1613768400, "temp": -23.0 }
+                    ]
+                }"#);
+        });
+
+        let location = Location::Coordinates { lat: 55.79, lon: 49.12 };
+        let report = OpenWeather::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), server.url(""))
+            .get_history(&location, NaiveDate::from_ymd(2021, 2, 20))
+            .await
+            .unwrap();
+
+        assert_eq!(report.temperature, -21.0);
+        assert_eq!(report.temperature_min, Some(-23.0));
+        assert_eq!(report.temperature_max, Some(-18.0));
+        mock.assert_async().await;
+        previous_day_mock.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn it_gives_up_on_slow_responses() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/weather");
+            then.status(200)
+                .delay(Duration::from_secs(2));
+        });
+
+        let report = OpenWeather::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), server.url(""))
+            .with_timeout(Duration::from_millis(100))
+            .get_current(&kazan())
+            .await;
+
+        assert!(matches!(report, Err(ProviderError::Timeout)));
     }
 
     #[actix_rt::test]
@@ -244,9 +734,26 @@ mod tests {
         });
 
         let key = "apikey".to_string();
-        let report = OpenWeather::new_with_prefix(key, server.url("")).get_current("kazan").await;
+        let report = OpenWeather::new_with_prefix(reqwest::Client::new(), key, server.url("")).get_current(&kazan()).await;
 
-        assert_eq!(report.is_err(), true);
+        assert!(matches!(report, Err(ProviderError::InvalidApiKey(Some(message))) if message.starts_with("Invalid API key")));
+    }
+
+    #[actix_rt::test]
+    async fn it_returns_unknown_location_for_missing_city() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/weather");
+            then.status(404)
+                .header("Content-Type", "application/json")
+                .body(r#"{"cod":"404","message":"city not found"}"#);
+        });
+
+        let key = "apikey".to_string();
+        let report = OpenWeather::new_with_prefix(reqwest::Client::new(), key, server.url("")).get_current(&kazan()).await;
+
+        assert!(matches!(report, Err(ProviderError::UnknownLocation)));
     }
 }
 
@@ -1,92 +1,216 @@
-use crate::WeatherReport;
+use crate::{Location, WeatherCondition, WeatherReport};
+use crate::errors::ProviderError;
+use super::{json_from_response, send_with_retry, Capabilities, RetryPolicy, WeatherProvider};
+use async_trait::async_trait;
+use chrono::{Duration as ChronoDuration, NaiveDate, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
 use reqwest::header::CONTENT_TYPE;
-use std::error::Error;
-use std::fmt;
+use serde::Deserialize;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Weatherbit {
+    client: reqwest::Client,
     api_key: String,
-    api_path_prefix: String
+    api_path_prefix: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy
 }
 
-#[derive(Debug)]
-struct WeatherbitJsonParseError;
-impl Error for WeatherbitJsonParseError {}
-impl fmt::Display for WeatherbitJsonParseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Failed to build weather report from weatherbit response json")
-    }
+// every endpoint answers with `data` array, which holds a single observation for current weather
+#[derive(Deserialize)]
+struct Response {
+    timezone: Option<String>,
+    data: Vec<Observation>
+}
+
+#[derive(Deserialize)]
+struct Observation {
+    ts: i64,
+    temp: f64,
+    timezone: Option<String>,
+    min_temp: Option<f64>,
+    max_temp: Option<f64>,
+    rh: Option<f64>,
+    wind_spd: Option<f64>,
+    wind_dir: Option<f64>,
+    pres: Option<f64>,
+    clouds: Option<f64>,
+    precip: Option<f64>,
+    weather: Option<Condition>
 }
 
-const API_PATH_PREFIX: &str = "http://api.weatherbit.io/v2.0/";
+#[derive(Deserialize)]
+struct Condition {
+    code: u32,
+    #[serde(default)]
+    description: String
+}
+
+const API_PATH_PREFIX: &str = "http://api.weatherbit.io/v2.0";
+const MAX_FORECAST_DAYS: usize = 16;
+const MAX_FORECAST_HOURS: usize = 48;
+const MAX_HISTORY_DAYS: usize = 365;
+
+#[async_trait(?Send)]
+impl WeatherProvider for Weatherbit {
+    fn name(&self) -> &'static str {
+        "weatherbit"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            current: true,
+            forecast: true,
+            max_forecast_days: MAX_FORECAST_DAYS,
+            hourly: true,
+            max_forecast_hours: MAX_FORECAST_HOURS,
+            history_days: MAX_HISTORY_DAYS
+        }
+    }
+
+    async fn get_current(&self, location: &Location) -> Result<WeatherReport, ProviderError> {
+        let full_path = format!("{}/current", self.api_path_prefix);
+        let query = self.location_query(location)?;
+        let response = self.get_raw(full_path, query).await?;
+        Self::report_from_current(response)
+    }
+
+    async fn get_forecast(&self, location: &Location, days_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let full_path = format!("{}/forecast/daily", self.api_path_prefix);
+        let mut query = self.location_query(location)?;
+        query.push(("days", days_count.to_string()));
+        let response = self.get_raw(full_path, query).await?;
+        Ok(Self::reports_from_response(response))
+    }
+
+    async fn get_hourly(&self, location: &Location, hours_count: usize) -> Result<Vec<WeatherReport>, ProviderError> {
+        let full_path = format!("{}/forecast/hourly", self.api_path_prefix);
+        let mut query = self.location_query(location)?;
+        query.push(("hours", hours_count.to_string()));
+        let response = self.get_raw(full_path, query).await?;
+        Ok(Self::reports_from_response(response))
+    }
+
+    async fn get_history(&self, location: &Location, date: NaiveDate) -> Result<WeatherReport, ProviderError> {
+        let full_path = format!("{}/history/daily", self.api_path_prefix);
+        let mut query = self.location_query(location)?;
+        query.push(("start_date", date.format("%Y-%m-%d").to_string()));
+        query.push(("end_date", (date + ChronoDuration::days(1)).format("%Y-%m-%d").to_string()));
+        let response = self.get_raw(full_path, query).await?;
+        Self::reports_from_response(response)
+            .into_iter()
+            .next()
+            .ok_or_else(|| ProviderError::Parse(format!("history response has no observations for {}", date)))
+    }
+}
 
 impl Weatherbit {
-    pub fn new(api_key: String) -> Self {
-        Self {  api_key, api_path_prefix: API_PATH_PREFIX.to_string() }
+    pub fn new(client: reqwest::Client, api_key: String) -> Self {
+        Self { client, api_key, api_path_prefix: API_PATH_PREFIX.to_string(), timeout: None, retry: RetryPolicy::default() }
     }
 
-    #[cfg(test)]
-    pub fn new_with_prefix(api_key: String, api_path_prefix: String) -> Self {
-        Self { api_key, api_path_prefix }
+    pub fn new_with_prefix(client: reqwest::Client, api_key: String, api_path_prefix: String) -> Self {
+        Self { client, api_key, api_path_prefix, timeout: None, retry: RetryPolicy::default() }
     }
 
-    pub async fn get_current(&self, city_name: &str) -> Result<WeatherReport, Box<dyn Error>> {
-        let full_path = format!("{}/current?key={}&city={}", self.api_path_prefix, self.api_key, city_name);
-        let raw_json = Self::get_raw(full_path).await?;
-        Self::parse_report_from_raw_json(raw_json)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
     }
 
-    pub async fn get_forecast(&self, city_name: &str, days_count: usize) -> Result<Vec<WeatherReport>, Box<dyn Error>> {
-        let full_path = format!("{}/forecast/daily?key={}&city={}&days={}",
-                                self.api_path_prefix, self.api_key, city_name, days_count);
-        let raw_json = Self::get_raw(full_path).await?;
-        Self::parse_report_array_from_raw_json(raw_json)
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
     }
 
-    async fn get_raw(full_path: String) -> Result<serde_json::Value, Box<dyn Error>> {
-        let client = reqwest::Client::new();
-        let raw_result = client
+    fn location_query(&self, location: &Location) -> Result<Vec<(&'static str, String)>, ProviderError> {
+        let query = match location {
+            Location::Coordinates { lat, lon } => vec![("lat", lat.to_string()), ("lon", lon.to_string())],
+            Location::PostalCode { zip, country } => vec![("postal_code", zip.clone()), ("country", country.clone())],
+            Location::City { name, country_code: Some(country_code) } => vec![("city", name.clone()), ("country", country_code.clone())],
+            Location::City { name, country_code: None } => vec![("city", name.clone())],
+            Location::ProviderCityId { provider, id } if provider == self.name() => vec![("city_id", id.clone())],
+            Location::ProviderCityId { .. } =>
+                return Err(ProviderError::UnsupportedLocation(location.clone()))
+        };
+        Ok(query)
+    }
+
+    async fn get_raw(&self, full_path: String, mut query: Vec<(&'static str, String)>) -> Result<Response, ProviderError> {
+        query.push(("units", "M".to_string()));
+        query.push(("key", self.api_key.clone()));
+        let mut request = self.client
             .get(&full_path)
-            .header(CONTENT_TYPE, "application/json")
-            .send()
-            .await?
-            .json()
-            .await?;
+            .query(&query)
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let response = send_with_retry(request, &self.retry).await?;
 
-        Ok(raw_result)
+        json_from_response(response).await
     }
 
-    fn parse_report_from_raw_json(data: serde_json::Value) -> Result<WeatherReport, Box<dyn Error>> {
-        Self::parse_report_from_weatherbit_json_struct(&data["data"][0])
+    fn report_from_current(response: Response) -> Result<WeatherReport, ProviderError> {
+        let observation = response.data
+            .into_iter()
+            .next()
+            .ok_or_else(|| ProviderError::Parse(String::from("current response has no observations")))?;
+        let timezone = observation.timezone.clone();
+        Ok(Self::report_from_observation(observation, timezone))
     }
 
-    fn parse_report_array_from_raw_json(data: serde_json::Value) -> Result<Vec<WeatherReport>, Box<dyn Error>> {
-        let array = data["data"].as_array();
-        if let Some(array) = array {
-            array.iter().map(Self::parse_report_from_weatherbit_json_struct).collect()
-        } else {
-            Err(WeatherbitJsonParseError.into())
-        }
+    fn reports_from_response(response: Response) -> Vec<WeatherReport> {
+        let timezone = response.timezone;
+        response.data
+            .into_iter()
+            .map(|observation| Self::report_from_observation(observation, timezone.clone()))
+            .collect()
     }
 
-    fn parse_report_from_weatherbit_json_struct(data: &serde_json::Value) -> Result<WeatherReport, Box<dyn Error>> {
-        let temp = data["temp"].as_f64();
-        let timestamp = data["ts"].as_i64();
-        if let (Some(temperature), Some(timestamp)) = (temp, timestamp) {
-            Ok(WeatherReport { temperature, unix_timestamp: timestamp })
-        } else {
-            Err(WeatherbitJsonParseError.into())
+    fn report_from_observation(observation: Observation, timezone: Option<String>) -> WeatherReport {
+        WeatherReport {
+            temperature: observation.temp,
+            unix_timestamp: observation.ts,
+            utc_offset: Self::utc_offset(timezone.as_deref(), observation.ts),
+            timezone,
+            temperature_min: observation.min_temp,
+            temperature_max: observation.max_temp,
+            humidity: observation.rh,
+            wind_speed: observation.wind_spd,
+            wind_direction: observation.wind_dir,
+            pressure: observation.pres,
+            cloud_cover: observation.clouds,
+            precipitation: observation.precip,
+            condition: observation.weather.map(|condition| WeatherCondition { code: condition.code, description: condition.description }),
+            ..Default::default()
         }
     }
-}
 
+    fn utc_offset(timezone: Option<&str>, timestamp: i64) -> i32 {
+        timezone
+            .and_then(|name| name.parse::<Tz>().ok())
+            .map(|tz| {
+                let utc_time = NaiveDateTime::from_timestamp(timestamp, 0);
+                tz.offset_from_utc_datetime(&utc_time).fix().local_minus_utc()
+            })
+            .unwrap_or(0)
+    }
+}
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use crate::weather_aggregator::weather_clients::parse_json;
     use httpmock::MockServer;
     use httpmock::Method::GET;
 
+    fn kazan() -> Location {
+        Location::City { name: "kazan".to_string(), country_code: None }
+    }
+
     #[test]
     fn it_deserializes_current_weather_valid_raw_json() {
         let raw_json = r#"
@@ -99,11 +223,9 @@ mod tests {
             "count":1
         }
         "#;
-        let json_value = serde_json::from_str(raw_json).unwrap();
-
 
         assert_eq!(
-            Weatherbit::parse_report_from_raw_json(json_value).unwrap().temperature,
+            parse_json(raw_json).and_then(Weatherbit::report_from_current).unwrap().temperature,
             -23.0
         )
     }
@@ -120,13 +242,10 @@ mod tests {
             "count":1
         }
         "#;
-        let json_value = serde_json::from_str(raw_json).unwrap();
 
+        let error = parse_json(raw_json).and_then(Weatherbit::report_from_current).unwrap_err();
 
-        assert_eq!(
-            Weatherbit::parse_report_from_raw_json(json_value).is_err(),
-            true
-        )
+        assert_eq!(error.to_string(), "invalid response: missing field `temp` at /data/0 near `{\"city_name\":\"Kazan\",\"tempo\":-23,\"ts\":1613928600}`");
     }
 
 
@@ -137,7 +256,9 @@ mod tests {
             "data": [
                 {
                     "ts": 1613941260,
-                    "temp": -27.3
+                    "temp": -27.3,
+                    "min_temp": -31.2,
+                    "max_temp": -24.5
                 },
                 {
                     "ts": 1614027660,
@@ -147,12 +268,35 @@ mod tests {
             "city_name": "Kazan"
         }
         "#;
-        let json_value = serde_json::from_str(raw_json).unwrap();
 
-        let parsed_reposts =Weatherbit::parse_report_array_from_raw_json(json_value).unwrap();
+        let parsed_reposts =parse_json(raw_json).map(Weatherbit::reports_from_response).unwrap();
 
         assert_eq!(parsed_reposts[0].temperature, -27.3);
-        assert_eq!(parsed_reposts[1].temperature, -29.6)
+        assert_eq!(parsed_reposts[0].temperature_min, Some(-31.2));
+        assert_eq!(parsed_reposts[0].temperature_max, Some(-24.5));
+        assert_eq!(parsed_reposts[1].temperature, -29.6);
+        assert_eq!(parsed_reposts[1].temperature_min, None)
+    }
+
+    #[test]
+    fn it_resolves_forecast_timezone_into_local_dates() {
+        let raw_json = r#"
+        {
+            "data": [
+                {
+                    "ts": 1613941260,
+                    "temp": -27.3
+                }
+            ],
+            "timezone": "Europe/Moscow"
+        }
+        "#;
+
+        let parsed_reports = parse_json(raw_json).map(Weatherbit::reports_from_response).unwrap();
+
+        assert_eq!(parsed_reports[0].utc_offset, 10800);
+        assert_eq!(parsed_reports[0].timezone.as_deref(), Some("Europe/Moscow"));
+        assert_eq!(parsed_reports[0].local_date(), chrono::NaiveDate::from_ymd(2021, 2, 22));
     }
 
     #[test]
@@ -172,10 +316,9 @@ mod tests {
             "city_name": "Kazan"
         }
         "#;
-        let json_value = serde_json::from_str(raw_json).unwrap();
 
         assert_eq!(
-            Weatherbit::parse_report_array_from_raw_json(json_value).is_err(),
+            parse_json(raw_json).map(Weatherbit::reports_from_response).is_err(),
             true
         )
     }
@@ -194,10 +337,132 @@ mod tests {
         });
 
         let key = "apikey".to_string();
-        let report = Weatherbit::new_with_prefix(key, server.url("")).get_current("kazan").await;
+        let report = Weatherbit::new_with_prefix(reqwest::Client::new(), key, server.url("")).get_current(&kazan()).await;
+
+        let report = report.unwrap();
+        assert_eq!(report.temperature, -23.0);
+        assert_eq!(report.utc_offset, 10800);
+        assert_eq!(report.humidity, Some(76.0));
+        assert_eq!(report.wind_speed, Some(2.0));
+        assert_eq!(report.wind_direction, Some(220.0));
+        assert_eq!(report.pressure, Some(1006.5));
+        assert_eq!(report.cloud_cover, Some(0.0));
+        assert_eq!(report.precipitation, Some(0.0));
+        assert_eq!(report.condition, Some(WeatherCondition { code: 800, description: "Clear sky".to_string() }));
+    }
+
+    #[actix_rt::test]
+    async fn it_translates_location_into_query_params() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/forecast/daily")
+                .query_param("postal_code", "75001")
+                .query_param("country", "FR")
+                .query_param("days", "3")
+                .query_param("key", "apikey");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{"data": [{"ts": 1613941260, "temp": 7.5}], "timezone": "Europe/Paris"}"#);
+        });
+
+        let location = Location::PostalCode { zip: "75001".to_string(), country: "FR".to_string() };
+        let forecast = Weatherbit::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), server.url("")).get_forecast(&location, 3).await;
+
+        assert_eq!(forecast.unwrap()[0].temperature, 7.5);
+        mock.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn it_fetches_hourly_forecast() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/forecast/hourly")
+                .query_param("city", "kazan")
+                .query_param("hours", "2");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{"data": [{"ts": 1614078000, "temp": -17.0}, {"ts": 1614081600, "temp": -18.0}], "timezone": "Europe/Moscow"}"#);
+        });
+
+        let forecast = Weatherbit::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), server.url(""))
+            .get_hourly(&kazan(), 2)
+            .await
+            .unwrap();
+
+        assert_eq!(forecast.len(), 2);
+        assert_eq!(forecast[1].temperature, -18.0);
+        assert_eq!(forecast[1].utc_offset, 10800);
+        mock.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn it_fetches_history_for_date() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/history/daily")
+                .query_param("city", "kazan")
+                .query_param("start_date", "2021-02-20")
+                .query_param("end_date", "2021-02-21");
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(r#"{"data": [{"ts": 1613768400, "temp": -21.4, "min_temp": -25.0, "max_temp": -18.2}], "timezone": "Europe/Moscow"}"#);
+        });
+
+        let report = Weatherbit::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), server.url(""))
+            .get_history(&kazan(), NaiveDate::from_ymd(2021, 2, 20))
+            .await
+            .unwrap();
+
+        assert_eq!(report.temperature, -21.4);
+        assert_eq!(report.temperature_min, Some(-25.0));
+        assert_eq!(report.local_date(), NaiveDate::from_ymd(2021, 2, 20));
+        mock.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn it_retries_server_errors() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/current");
+            then.status(503);
+        });
+
+        let retry = RetryPolicy { max_retries: 2, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(5), deadline: None };
+        let report = Weatherbit::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), server.url(""))
+            .with_retry(retry)
+            .get_current(&kazan())
+            .await;
 
-        assert_eq!(report.is_ok(), true);
-        assert_eq!(report.unwrap().temperature, -23.0);
+        assert!(matches!(report, Err(ProviderError::Unavailable(_))));
+        mock.assert_hits_async(3).await;
+    }
+
+    #[actix_rt::test]
+    async fn it_stops_retrying_after_deadline() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/current");
+            then.status(503)
+                .delay(Duration::from_millis(50));
+        });
+
+        let retry = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+            deadline: Some(Duration::from_millis(120))
+        };
+        let report = Weatherbit::new_with_prefix(reqwest::Client::new(), "apikey".to_string(), server.url(""))
+            .with_retry(retry)
+            .get_current(&kazan())
+            .await;
+
+        assert!(matches!(report, Err(ProviderError::Timeout)));
     }
 
     #[actix_rt::test]
@@ -205,7 +470,7 @@ mod tests {
         let server = MockServer::start();
         server.mock(|when, then| {
             when.method(GET)
-                .path("/weather");
+                .path("/current");
 
             let json = std::fs::read_to_string("./tests/fixtures/weatherbit_invalid_key.json").unwrap();
             then.status(403)
@@ -214,8 +479,23 @@ mod tests {
         });
 
         let key = "apikey".to_string();
-        let report = Weatherbit::new_with_prefix(key, server.url("")).get_current("kazan").await;
+        let report = Weatherbit::new_with_prefix(reqwest::Client::new(), key, server.url("")).get_current(&kazan()).await;
+
+        assert!(matches!(report, Err(ProviderError::InvalidApiKey(Some(message))) if message == "API key not valid, or not yet activated."));
+    }
+
+    #[actix_rt::test]
+    async fn it_returns_unknown_location_for_empty_response() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/current");
+            then.status(204);
+        });
+
+        let key = "apikey".to_string();
+        let report = Weatherbit::new_with_prefix(reqwest::Client::new(), key, server.url("")).get_current(&kazan()).await;
 
-        assert_eq!(report.is_err(), true);
+        assert!(matches!(report, Err(ProviderError::UnknownLocation)));
     }
 }
@@ -32,10 +32,12 @@ async fn get_forecast_weather() {
     assert!(response.status().is_success());
 }
 
+#[allow(clippy::let_underscore_future)]
 fn spawn_app() -> String {
     let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
     let port = listener.local_addr().unwrap().port();
-    let server = weather_reports::run(listener).expect("Failed to launch the app");
+    let config = weather_reports::Config::load().expect("Failed to load config");
+    let server = weather_reports::run(listener, config).expect("Failed to launch the app");
     let _ = tokio::spawn(server);
     format!("http://127.0.0.1:{}", port)
 }